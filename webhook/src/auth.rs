@@ -0,0 +1,325 @@
+use axum::http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time;
+use tokio::sync::Mutex;
+
+// a JWT is valid for at most 10 minutes, the issue time is backdated against clock drift
+const JWT_BACKDATE: u64 = 60;
+const JWT_LIFETIME: u64 = 60 * 8;
+// `work` runs with the installation token, it has to outlive the scan
+const TOKEN_REFRESH_MARGIN: u64 = 60 * 10;
+
+/// Source of the current time in seconds since the UNIX epoch.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Jwt(jsonwebtoken::errors::Error),
+    Request(reqwest::Error),
+    Status(reqwest::StatusCode),
+    Expiry(chrono::ParseError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Jwt(e) => write!(f, "cannot sign the app JWT: {}", e),
+            Error::Request(e) => write!(f, "cannot request an installation token: {}", e),
+            Error::Status(s) => write!(f, "installation token is refused: {}", s),
+            Error::Expiry(e) => write!(f, "installation token has a wrong expiry: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JwtClaims {
+    /// The time that this JWT was issued
+    iat: u64,
+    // JWT expiration time
+    exp: u64,
+    // GitHub App's identifier number
+    iss: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccessToken {
+    token: String,
+    expires_at: String,
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    token: String,
+    expires_at: u64,
+}
+
+/// A GitHub App on one GitHub instance. JWTs are minted for every request to the
+/// app API, installation tokens are shared until shortly before they expire.
+pub struct GithubApp {
+    id: String,
+    key: jsonwebtoken::EncodingKey,
+    api_url: String,
+    client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+    tokens: Mutex<HashMap<i64, CachedToken>>,
+}
+
+impl GithubApp {
+    pub fn new(id: &str, key: &[u8], api_url: &str) -> Result<GithubApp, Error> {
+        GithubApp::with_clock(id, key, api_url, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        id: &str,
+        key: &[u8],
+        api_url: &str,
+        clock: Arc<dyn Clock>,
+    ) -> Result<GithubApp, Error> {
+        let key = jsonwebtoken::EncodingKey::from_rsa_pem(key).map_err(Error::Jwt)?;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::ACCEPT,
+            HeaderValue::from_static("application/vnd.github+json"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .user_agent("copyright-webhook/0.1.0")
+            .build()
+            .map_err(Error::Request)?;
+        Ok(GithubApp {
+            id: id.to_string(),
+            key,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            client,
+            clock,
+            tokens: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn jwt(&self) -> Result<String, Error> {
+        let now = self.clock.now();
+        let claims = JwtClaims {
+            iat: now - JWT_BACKDATE,
+            exp: now + JWT_LIFETIME,
+            iss: self.id.to_string(),
+        };
+        let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+        jsonwebtoken::encode(&header, &claims, &self.key).map_err(Error::Jwt)
+    }
+
+    /// Token of the installation, it's requested again when it's about to expire.
+    /// Concurrent callers wait for a single request instead of racing each other.
+    pub async fn installation_token(&self, installation: i64) -> Result<String, Error> {
+        let mut tokens = self.tokens.lock().await;
+        if let Some(cached) = tokens.get(&installation) {
+            if self.clock.now() + TOKEN_REFRESH_MARGIN < cached.expires_at {
+                return Ok(cached.token.to_string());
+            }
+        }
+        tracing::debug!("request token of installation {}", installation);
+        let response = self
+            .client
+            .post(format!(
+                "{}/app/installations/{}/access_tokens",
+                self.api_url, installation
+            ))
+            .bearer_auth(self.jwt()?)
+            .send()
+            .await
+            .map_err(Error::Request)?;
+        if !response.status().is_success() {
+            return Err(Error::Status(response.status()));
+        }
+        let access: AccessToken = response.json().await.map_err(Error::Request)?;
+        let expires_at = chrono::DateTime::parse_from_rfc3339(&access.expires_at)
+            .map_err(Error::Expiry)?
+            .timestamp() as u64;
+        tokens.insert(
+            installation,
+            CachedToken {
+                token: access.token.to_string(),
+                expires_at,
+            },
+        );
+        Ok(access.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use chrono::TimeZone;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const KEY: &[u8] = include_bytes!("../testdata/github-app.pem");
+    const PUBLIC_KEY: &[u8] = include_bytes!("../testdata/github-app.pub.pem");
+    const START: u64 = 1_700_000_000;
+    const TOKEN_LIFETIME: u64 = 60 * 60;
+
+    struct FakeClock(AtomicU64);
+
+    impl FakeClock {
+        fn advance(&self, seconds: u64) {
+            self.0.fetch_add(seconds, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Token endpoint which hands out tokens valid for an hour of the fake clock.
+    struct Endpoint {
+        clock: Arc<FakeClock>,
+        refused: bool,
+        // installation and claims of the JWT of every request
+        requests: std::sync::Mutex<Vec<(i64, JwtClaims)>>,
+    }
+
+    async fn access_tokens(
+        State(endpoint): State<Arc<Endpoint>>,
+        Path(installation): Path<i64>,
+        headers: HeaderMap,
+    ) -> Result<Json<AccessToken>, StatusCode> {
+        let bearer = headers
+            .get("Authorization")
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        let key = jsonwebtoken::DecodingKey::from_rsa_pem(PUBLIC_KEY).unwrap();
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+        // the expiry is checked against the fake clock by the tests
+        validation.validate_exp = false;
+        let claims = jsonwebtoken::decode::<JwtClaims>(bearer, &key, &validation)
+            .map_err(|_| StatusCode::UNAUTHORIZED)?
+            .claims;
+        let mut requests = endpoint.requests.lock().unwrap();
+        requests.push((installation, claims));
+        if endpoint.refused {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let expires_at = chrono::Utc
+            .timestamp_opt((endpoint.clock.now() + TOKEN_LIFETIME) as i64, 0)
+            .unwrap();
+        Ok(Json(AccessToken {
+            token: format!("token-{}-{}", installation, requests.len()),
+            expires_at: expires_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        }))
+    }
+
+    async fn serve(refused: bool) -> (Arc<FakeClock>, Arc<Endpoint>, GithubApp) {
+        let clock = Arc::new(FakeClock(AtomicU64::new(START)));
+        let endpoint = Arc::new(Endpoint {
+            clock: clock.clone(),
+            refused,
+            requests: std::sync::Mutex::new(Vec::new()),
+        });
+        let router = Router::new()
+            .route("/app/installations/:id/access_tokens", post(access_tokens))
+            .with_state(endpoint.clone());
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        let app = GithubApp::with_clock("42", KEY, &url, clock.clone()).unwrap();
+        (clock, endpoint, app)
+    }
+
+    #[tokio::test]
+    async fn token_is_shared_until_it_is_about_to_expire() {
+        let (clock, endpoint, app) = serve(false).await;
+        assert_eq!(app.installation_token(7).await.unwrap(), "token-7-1");
+        clock.advance(TOKEN_LIFETIME - TOKEN_REFRESH_MARGIN - 1);
+        assert_eq!(app.installation_token(7).await.unwrap(), "token-7-1");
+        assert_eq!(endpoint.requests.lock().unwrap().len(), 1);
+        clock.advance(1);
+        assert_eq!(app.installation_token(7).await.unwrap(), "token-7-2");
+        assert_eq!(endpoint.requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn jwt_is_minted_for_every_request() {
+        let (clock, endpoint, app) = serve(false).await;
+        app.installation_token(7).await.unwrap();
+        // long after the first JWT expired
+        clock.advance(TOKEN_LIFETIME * 5);
+        app.installation_token(7).await.unwrap();
+        let requests = endpoint.requests.lock().unwrap();
+        let issued: Vec<(u64, u64)> = requests.iter().map(|(_, x)| (x.iat, x.exp)).collect();
+        let later = START + TOKEN_LIFETIME * 5;
+        assert_eq!(
+            issued,
+            vec![
+                (START - JWT_BACKDATE, START + JWT_LIFETIME),
+                (later - JWT_BACKDATE, later + JWT_LIFETIME),
+            ]
+        );
+        assert!(requests.iter().all(|(_, x)| x.iss == "42"));
+    }
+
+    #[tokio::test]
+    async fn installations_have_their_own_tokens() {
+        let (_, endpoint, app) = serve(false).await;
+        assert_eq!(app.installation_token(7).await.unwrap(), "token-7-1");
+        assert_eq!(app.installation_token(8).await.unwrap(), "token-8-2");
+        assert_eq!(app.installation_token(7).await.unwrap(), "token-7-1");
+        assert_eq!(app.installation_token(8).await.unwrap(), "token-8-2");
+        let installations: Vec<i64> = endpoint
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(x, _)| *x)
+            .collect();
+        assert_eq!(installations, vec![7, 8]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_request() {
+        let (_, endpoint, app) = serve(false).await;
+        let app = Arc::new(app);
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let app = app.clone();
+                tokio::spawn(async move { app.installation_token(7).await.unwrap() })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), "token-7-1");
+        }
+        assert_eq!(endpoint.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refused_token_is_not_cached() {
+        let (_, endpoint, app) = serve(true).await;
+        let err = app.installation_token(7).await.unwrap_err();
+        assert!(matches!(err, Error::Status(StatusCode::FORBIDDEN)));
+        assert!(app.installation_token(7).await.is_err());
+        assert_eq!(endpoint.requests.lock().unwrap().len(), 2);
+    }
+}
@@ -1,7 +1,10 @@
-use crate::util;
+use crate::metrics::METRICS;
+use crate::queue::{Credentials, EnvsFuture, Job, Queue};
 use axum::extract::State;
+use axum::http::HeaderMap;
 use axum::{routing::post, Json};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Ref {
@@ -33,35 +36,49 @@ impl Bitbucket {
     }
 }
 
+impl Credentials for Bitbucket {
+    fn envs<'a>(&'a self, _job: &'a Job) -> EnvsFuture<'a> {
+        Box::pin(async move {
+            Ok(vec![
+                ("GIT_USERNAME", self.username.to_string()),
+                ("GIT_PASSWORD", self.password.to_string()),
+            ])
+        })
+    }
+}
+
+// events of pull-requests which Bitbucket Server sends
+const ACTIONS: &[&str] = &["pr:opened", "pr:from_ref_updated", "pr:modified"];
+
 async fn bitbucket_event_handler(
-    State(bitbucket): State<Bitbucket>,
+    State(queue): State<Arc<Queue>>,
+    headers: HeaderMap,
     Json(event): Json<Event>,
 ) -> &'static str {
-    let checked = util::protect_enter(
-        "bitbucket",
-        &event.to.project,
-        &event.to.repository,
-        event.id,
-    );
-    if !checked {
-        tracing::warn!("the same request is running");
+    let action = headers
+        .get("X-Event-Key")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default();
+    METRICS.event("bitbucket", action, ACTIONS);
+    let job = Job {
+        scm: "bitbucket".to_string(),
+        project: event.to.project,
+        repository: event.to.repository,
+        id: event.id,
+        commit: event.from.commit,
+        host: None,
+        installation: None,
+    };
+    if !queue.push(job) {
+        tracing::warn!("the same request is queued");
         return "duplicated request";
     }
-    tokio::task::spawn(async move {
-        util::run_command(
-            &bitbucket.username,
-            &bitbucket.password,
-            event.id,
-            &event.to.project,
-            &event.to.repository,
-            "bitbucket",
-        )
-        .await
-    });
     "ok"
 }
 
-pub fn create(u: &str, p: &str) -> axum::Router<Bitbucket> {
-    let bitbucket = Bitbucket::new(u, p);
-    axum::Router::with_state(bitbucket).route("/hook", post(bitbucket_event_handler))
+pub fn create(u: &str, p: &str, queue: Arc<Queue>) -> axum::Router {
+    queue.register("bitbucket", Arc::new(Bitbucket::new(u, p)));
+    axum::Router::new()
+        .route("/hook", post(bitbucket_event_handler))
+        .with_state(queue)
 }
@@ -0,0 +1,115 @@
+use crate::metrics::METRICS;
+use crate::queue::{Credentials, EnvsFuture, Job, Queue};
+use axum::body::Body;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::{routing::post, Json, RequestExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Commit {
+    hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Repository {
+    // `{workspace}/{slug}`
+    full_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Ref {
+    commit: Commit,
+    repository: Repository,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PullRequest {
+    id: u32,
+    source: Ref,
+    destination: Ref,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Event {
+    pullrequest: PullRequest,
+}
+
+/// The account of the app password.
+#[derive(Debug, Clone)]
+pub struct BitbucketCloud {
+    username: String,
+    password: String,
+}
+
+impl BitbucketCloud {
+    pub fn new(u: &str, p: &str) -> BitbucketCloud {
+        BitbucketCloud {
+            username: u.to_string(),
+            password: p.to_string(),
+        }
+    }
+}
+
+impl Credentials for BitbucketCloud {
+    fn envs<'a>(&'a self, _job: &'a Job) -> EnvsFuture<'a> {
+        Box::pin(async move {
+            Ok(vec![
+                ("GIT_USERNAME", self.username.to_string()),
+                ("GIT_PASSWORD", self.password.to_string()),
+            ])
+        })
+    }
+}
+
+// events of pull-requests which are scanned
+const ACTIONS: &[&str] = &["pullrequest:created", "pullrequest:updated"];
+
+async fn bitbucket_cloud_event_handler(
+    State(queue): State<Arc<Queue>>,
+    req: Request<Body>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let event_key = req
+        .headers()
+        .get("X-Event-Key")
+        .ok_or((StatusCode::BAD_REQUEST, "event type is missing".to_string()))?;
+    let action = event_key.to_str().unwrap_or_default();
+    METRICS.event("bitbucket-cloud", action, ACTIONS);
+    if !ACTIONS.contains(&action) {
+        return Err((StatusCode::BAD_REQUEST, "event type is wrong".to_string()));
+    }
+    let Json(event): Json<Event> = req
+        .extract()
+        .await
+        .map_err(|e: JsonRejection| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let pull_request = event.pullrequest;
+    let (workspace, repository) = pull_request
+        .destination
+        .repository
+        .full_name
+        .split_once('/')
+        .ok_or((StatusCode::BAD_REQUEST, "repository is wrong".to_string()))?;
+    let job = Job {
+        scm: "bitbucket-cloud".to_string(),
+        project: workspace.to_string(),
+        repository: repository.to_string(),
+        id: pull_request.id,
+        commit: pull_request.source.commit.hash,
+        host: None,
+        installation: None,
+    };
+    if !queue.push(job) {
+        tracing::warn!("the same request is queued");
+        return Ok("duplicated request");
+    }
+    Ok("ok")
+}
+
+pub fn create(u: &str, p: &str, queue: Arc<Queue>) -> axum::Router {
+    queue.register("bitbucket-cloud", Arc::new(BitbucketCloud::new(u, p)));
+    axum::Router::new()
+        .route("/hook", post(bitbucket_cloud_event_handler))
+        .with_state(queue)
+}
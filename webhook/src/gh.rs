@@ -1,4 +1,6 @@
-use crate::util;
+use crate::auth::GithubApp;
+use crate::metrics::METRICS;
+use crate::queue::{Credentials, EnvsFuture, Job, Queue};
 use axum::body::Body;
 use axum::extract::State;
 use axum::http::HeaderValue;
@@ -8,27 +10,82 @@ use axum::{
     Json, RequestExt,
 };
 use serde::{Deserialize, Serialize};
-use std::time;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AccessToken {
-    token: String,
-    expires_at: String,
+const GITHUB_HOST: &str = "github.com";
+const GITHUB_API_URL: &str = "https://api.github.com";
+// actions of pull-request events which are scanned
+const ACTIONS: &[&str] = &["opened", "synchronize"];
+
+/// A GitHub instance in the hosts file, github.com or a GitHub Enterprise Server.
+/// Unset URLs are derived from the host and the app of `GITHUB_APPID` is used by default.
+#[derive(Debug, Deserialize)]
+pub struct HostConfig {
+    host: String,
+    api_url: Option<String>,
+    graphql_url: Option<String>,
+    clone_url: Option<String>,
+    app_id: Option<String>,
+    app_key_file: Option<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GithubAccount {
-    login: String,
-    id: u32,
+/// Endpoints of a GitHub instance and the app installed on it.
+struct Host {
+    api_url: String,
+    graphql_url: Option<String>,
+    clone_url: Option<String>,
+    app: GithubApp,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GithubAppInstallation {
-    id: u32,
-    access_tokens_url: String,
-    account: GithubAccount,
-    app_id: u32,
-    target_id: u32,
+/// The GitHub instances which are served.
+struct Github {
+    hosts: HashMap<String, Host>,
+}
+
+impl Credentials for Github {
+    fn envs<'a>(&'a self, job: &'a Job) -> EnvsFuture<'a> {
+        Box::pin(async move {
+            let host_name = job.host.as_deref().unwrap_or(GITHUB_HOST);
+            let host = self
+                .hosts
+                .get(host_name)
+                .ok_or(format!("GitHub host {} is not configured", host_name))?;
+            let installation = job.installation.ok_or("installation is missing")?;
+            let token = host
+                .app
+                .installation_token(installation)
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut envs = vec![
+                ("GIT_USERNAME", "x-access-token".to_string()),
+                ("GIT_PASSWORD", token),
+            ];
+            envs.extend(host.envs());
+            Ok(envs)
+        })
+    }
+}
+
+#[derive(Clone)]
+struct HandlerState {
+    github: Arc<Github>,
+    queue: Arc<Queue>,
+}
+
+impl Host {
+    /// Environment of `work`, the URLs which are not set are derived by it.
+    fn envs(&self) -> Vec<(&'static str, String)> {
+        let mut envs = vec![("GITHUB_API_URL", self.api_url.to_string())];
+        if let Some(url) = &self.graphql_url {
+            envs.push(("GITHUB_GRAPHQL_URL", url.to_string()));
+        }
+        if let Some(url) = &self.clone_url {
+            envs.push(("GITHUB_CLONE_URL", url.to_string()));
+        }
+        envs
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -71,46 +128,8 @@ struct GithubPayload {
     installation: Installation,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct JwtClaims {
-    /// The time that this JWT was issued
-    iat: u64,
-    // JWT expiration time
-    exp: u64,
-    // GitHub App's identifier number
-    iss: String,
-}
-
-impl JwtClaims {
-    fn new(app_id: &str) -> JwtClaims {
-        let now = time::SystemTime::now()
-            .duration_since(time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        JwtClaims {
-            // The time that this JWT was issued (now)
-            iat: now - 10,
-            // JWT expiration time (8 minute from now)
-            exp: now + 60 * 8,
-            // GitHub App's identifier number
-            iss: app_id.to_string(),
-        }
-    }
-}
-
-fn get_id_token(app_id: &str, key: &[u8]) -> String {
-    let claims = JwtClaims::new(app_id);
-    let header = jsonwebtoken::Header {
-        alg: jsonwebtoken::Algorithm::RS256,
-        ..Default::default()
-    };
-    let private_key = jsonwebtoken::EncodingKey::from_rsa_pem(key).unwrap();
-    let token = jsonwebtoken::encode(&header, &claims, &private_key);
-    return token.unwrap();
-}
-
 async fn github_event_handler(
-    client: State<reqwest::Client>,
+    State(state): State<HandlerState>,
     req: Request<Body>,
 ) -> Result<&'static str, (StatusCode, String)> {
     let event_type = req
@@ -118,64 +137,95 @@ async fn github_event_handler(
         .get("X-GitHub-Event")
         .ok_or((StatusCode::BAD_REQUEST, "event type is missing".to_string()))?;
     if HeaderValue::from_static("pull_request") != event_type {
+        METRICS.event("github", "", ACTIONS);
         return Err((StatusCode::BAD_REQUEST, "event type is wrong".to_string()));
     }
+    // GitHub Enterprise Server names itself, github.com doesn't
+    let host_name = match req.headers().get("X-GitHub-Enterprise-Host") {
+        Some(value) => value
+            .to_str()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+            .to_string(),
+        None => GITHUB_HOST.to_string(),
+    };
+    if !state.github.hosts.contains_key(&host_name) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("GitHub host {} is not configured", host_name),
+        ));
+    }
     let Json(payload): Json<GithubPayload> = req.extract().await.map_err(internal_error)?;
-    if payload.action != "synchronize" && payload.action != "opened" {
+    METRICS.event("github", &payload.action, ACTIONS);
+    if !ACTIONS.contains(&payload.action.as_str()) {
         return Err((StatusCode::BAD_REQUEST, "action type is wrong".to_string()));
     }
     let repository = payload.pull_request.base.repo.name;
     let owner = payload.pull_request.base.repo.owner.login;
-    let yes = util::protect_enter("github", &owner, &repository, payload.number);
-    if !yes {
-        tracing::warn!("the same request is running");
+    let job = Job {
+        scm: "github".to_string(),
+        project: owner,
+        repository,
+        id: payload.number,
+        commit: payload.pull_request.head.sha,
+        host: Some(host_name),
+        installation: Some(payload.installation.id),
+    };
+    if !state.queue.push(job) {
+        tracing::warn!("the same request is queued");
         return Ok("duplicated request");
     }
-    let response = client
-        .post(format!(
-            "https://api.github.com/app/installations/{}/access_tokens",
-            payload.installation.id
-        ))
-        .send()
-        .await
-        .map_err(internal_error)?;
-    let access: AccessToken = response.json().await.map_err(internal_error)?;
-    tokio::task::spawn(async move {
-        util::run_command(
-            "x-access-token",
-            &access.token,
-            payload.number,
-            &owner,
-            &repository,
-            "github",
-        )
-        .await
-    });
     Ok("ok")
 }
 
-pub fn create(id: &str, key: &[u8]) -> axum::Router<reqwest::Client> {
-    let token = get_id_token(id, key);
-    let bearer = format!("Bearer {}", token);
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::AUTHORIZATION,
-        HeaderValue::from_str(&bearer).unwrap(),
-    );
-    headers.insert(
-        reqwest::header::ACCEPT,
-        HeaderValue::from_static("application/vnd.github+json"),
-    );
-    headers.insert(
-        reqwest::header::CONTENT_TYPE,
-        HeaderValue::from_static("application/json"),
+/// Load the GitHub instances served besides github.com from a JSON file.
+pub fn load_hosts(path: &str) -> Vec<HostConfig> {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    serde_json::from_str(&content).unwrap_or_else(|e| panic!("{}: {}", path, e))
+}
+
+/// github.com is always served, the hosts file may override it, e.g. with a local fake.
+pub fn create(id: &str, key: &[u8], configs: Vec<HostConfig>, queue: Arc<Queue>) -> axum::Router {
+    let mut hosts: HashMap<String, Host> = HashMap::new();
+    hosts.insert(
+        GITHUB_HOST.to_string(),
+        Host {
+            api_url: GITHUB_API_URL.to_string(),
+            graphql_url: None,
+            clone_url: None,
+            app: GithubApp::new(id, key, GITHUB_API_URL).expect("GITHUB_APPKEY"),
+        },
     );
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .user_agent("copyright-webhook/0.1.0")
-        .build()
-        .unwrap();
-    axum::Router::with_state(client).route("/hook", post(github_event_handler))
+    for config in configs {
+        let api_url = match config.api_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None if config.host == GITHUB_HOST => GITHUB_API_URL.to_string(),
+            None => format!("https://{}/api/v3", config.host),
+        };
+        let app_key = match &config.app_key_file {
+            Some(path) => {
+                std::fs::read(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+            }
+            None => key.to_vec(),
+        };
+        let app_id = config.app_id.as_deref().unwrap_or(id);
+        let host_name = config.host;
+        let app = GithubApp::new(app_id, &app_key, &api_url)
+            .unwrap_or_else(|e| panic!("{}: {}", host_name, e));
+        hosts.insert(
+            host_name,
+            Host {
+                api_url,
+                graphql_url: config.graphql_url,
+                clone_url: config.clone_url,
+                app,
+            },
+        );
+    }
+    let github = Arc::new(Github { hosts });
+    queue.register("github", github.clone());
+    axum::Router::new()
+        .route("/hook", post(github_event_handler))
+        .with_state(HandlerState { github, queue })
 }
 
 fn internal_error<E>(err: E) -> (StatusCode, String)
@@ -0,0 +1,228 @@
+use crate::queue::{Entry, Queue, Status};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{middleware, routing::get, Json, Router};
+use chrono::TimeZone;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+struct JobSummary {
+    seq: u64,
+    scm: String,
+    project: String,
+    repository: String,
+    id: u32,
+    commit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    status: Status,
+    queued_at: Option<String>,
+    started_at: Option<String>,
+    finished_at: Option<String>,
+    /// Seconds which `work` has run so far
+    duration: Option<u64>,
+    code: Option<i32>,
+    result: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct JobDetail {
+    #[serde(flatten)]
+    summary: JobSummary,
+    stdout: String,
+    stderr: String,
+    /// JSON report of `work`
+    report: Option<serde_json::Value>,
+}
+
+// the records of older versions have no time
+fn format_time(time: u64) -> Option<String> {
+    if time == 0 {
+        return None;
+    }
+    chrono::Utc
+        .timestamp_opt(time as i64, 0)
+        .single()
+        .map(|x| x.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+impl From<Entry> for JobSummary {
+    fn from(entry: Entry) -> Self {
+        let duration = match (entry.started_at, entry.finished_at) {
+            // the clock may be set back while a job runs
+            (Some(started), Some(finished)) if started > 0 => {
+                Some(finished.saturating_sub(started))
+            }
+            (Some(started), None) if started > 0 => {
+                Some((chrono::Utc::now().timestamp() as u64).saturating_sub(started))
+            }
+            _ => None,
+        };
+        JobSummary {
+            seq: entry.seq,
+            status: entry.status,
+            queued_at: format_time(entry.queued_at),
+            started_at: entry.started_at.and_then(format_time),
+            finished_at: entry.finished_at.and_then(format_time),
+            duration,
+            code: entry.code,
+            result: entry.result(),
+            scm: entry.job.scm,
+            project: entry.job.project,
+            repository: entry.job.repository,
+            id: entry.job.id,
+            commit: entry.job.commit,
+            host: entry.job.host,
+        }
+    }
+}
+
+async fn list_jobs(State(queue): State<Arc<Queue>>) -> Json<Vec<JobSummary>> {
+    Json(queue.entries().into_iter().map(JobSummary::from).collect())
+}
+
+async fn read_output(queue: &Queue, seq: u64, stream: &str) -> String {
+    match tokio::fs::read(queue.output_path(seq, stream)).await {
+        Ok(x) => String::from_utf8_lossy(&x).to_string(),
+        // a queued job has no output yet
+        Err(_) => String::new(),
+    }
+}
+
+async fn get_job(
+    State(queue): State<Arc<Queue>>,
+    Path(seq): Path<u64>,
+) -> Result<Json<JobDetail>, (StatusCode, String)> {
+    let entry = queue
+        .entry(seq)
+        .ok_or((StatusCode::NOT_FOUND, format!("job {} is not found", seq)))?;
+    Ok(Json(JobDetail {
+        summary: JobSummary::from(entry),
+        stdout: read_output(&queue, seq, "stdout").await,
+        stderr: read_output(&queue, seq, "stderr").await,
+        report: serde_json::from_str(&read_output(&queue, seq, "json").await).ok(),
+    }))
+}
+
+/// Jobs of the queue for operators, they're guarded by the admin token.
+pub fn create(token: &str, queue: Arc<Queue>) -> Router {
+    Router::new()
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:seq", get(get_job))
+        .route_layer(middleware::from_fn_with_state(
+            token.to_string(),
+            crate::util::admin_middleware,
+        ))
+        .with_state(queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::Job;
+    use std::net::SocketAddr;
+
+    const TOKEN: &str = "admin-token";
+
+    /// The job API of a queue with two jobs, the first has its output.
+    async fn serve(dir: &tempfile::TempDir) -> String {
+        let queue =
+            Queue::open(&dir.path().join("jobs.log"), &dir.path().join("output"), 1).unwrap();
+        assert!(queue.push(Job::fixture("jobs-test", "repository", 1, "c0")));
+        assert!(queue.push(Job::fixture("jobs-test", "repository", 2, "c1")));
+        std::fs::write(queue.output_path(0, "stdout"), "scanned").unwrap();
+        std::fs::write(queue.output_path(0, "json"), r#"{"findings":[]}"#).unwrap();
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(create(TOKEN, Arc::new(queue)).into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    async fn get(url: &str, token: Option<&str>) -> (StatusCode, serde_json::Value) {
+        let mut request = reqwest::Client::new().get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let body = response.text().await.unwrap();
+        (status, serde_json::from_str(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn jobs_need_the_admin_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = serve(&dir).await;
+        for path in ["/jobs", "/jobs/0"] {
+            let path = format!("{}{}", url, path);
+            assert_eq!(get(&path, None).await.0, StatusCode::UNAUTHORIZED);
+            assert_eq!(get(&path, Some("wrong")).await.0, StatusCode::FORBIDDEN);
+            // a token of the same length is refused like any other
+            assert_eq!(
+                get(&path, Some("admin-tokem")).await.0,
+                StatusCode::FORBIDDEN
+            );
+            assert_eq!(get(&path, Some(TOKEN)).await.0, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_are_listed_latest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = serve(&dir).await;
+        let (status, jobs) = get(&format!("{}/jobs", url), Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+        let jobs = jobs.as_array().unwrap();
+        let listed: Vec<(u64, u64, &str)> = jobs
+            .iter()
+            .map(|x| {
+                (
+                    x["seq"].as_u64().unwrap(),
+                    x["id"].as_u64().unwrap(),
+                    x["status"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(listed, vec![(1, 2, "queued"), (0, 1, "queued")]);
+        assert_eq!(jobs[0]["commit"], "c1");
+        assert!(jobs[0]["queued_at"].is_string());
+        assert!(jobs[0]["started_at"].is_null());
+        assert!(jobs[0]["result"].is_null());
+        // only the detail has the output
+        assert!(jobs[0].get("stdout").is_none());
+    }
+
+    #[tokio::test]
+    async fn job_has_its_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = serve(&dir).await;
+        let (status, job) = get(&format!("{}/jobs/0", url), Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(job["seq"], 0);
+        assert_eq!(job["scm"], "jobs-test");
+        assert_eq!(job["stdout"], "scanned");
+        assert_eq!(job["stderr"], "");
+        assert_eq!(job["report"], serde_json::json!({"findings": []}));
+        let (_, job) = get(&format!("{}/jobs/1", url), Some(TOKEN)).await;
+        assert_eq!(job["stdout"], "");
+        assert!(job["report"].is_null());
+        let (status, _) = get(&format!("{}/jobs/7", url), Some(TOKEN)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn duration_is_never_negative() {
+        let entry = Entry {
+            seq: 0,
+            job: Job::fixture("jobs-test", "repository", 1, "c0"),
+            status: Status::Finished,
+            queued_at: 1_700_000_000,
+            started_at: Some(1_700_000_060),
+            finished_at: Some(1_700_000_030),
+            code: Some(0),
+        };
+        assert_eq!(JobSummary::from(entry).duration, Some(0));
+    }
+}
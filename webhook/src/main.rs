@@ -1,9 +1,15 @@
+mod auth;
 mod bb;
+mod bbc;
 mod gh;
+mod jobs;
+mod metrics;
+mod queue;
 mod util;
 use axum::routing::get;
 use axum::{middleware, Router};
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() {
@@ -19,20 +25,58 @@ async fn main() {
         std::env::var("BITBUCKET_PASSWORD").expect("BITBUCKET_PASSWORD");
     let git_app_id = std::env::var("GITHUB_APPID").expect("GITHUB_APPID");
     let git_app_key = std::env::var("GITHUB_APPKEY").expect("GITHUB_APPKEY");
+    // GitHub Enterprise Servers and overrides of github.com
+    let git_hosts = match std::env::var("GITHUB_HOSTS") {
+        Ok(path) => gh::load_hosts(&path),
+        Err(_) => Vec::new(),
+    };
+    // number of scans which run at the same time
+    let workers: usize = match std::env::var("WORKERS") {
+        Ok(x) => x.parse().expect("WORKERS"),
+        Err(_) => 2,
+    };
 
-    let bb = bb::create(&bitbucket_username, &bitbucket_password);
-    let gh = gh::create(&git_app_id, git_app_key.as_bytes());
+    let data = util::data_dir();
+    std::fs::create_dir_all(&data).expect("data");
+    let queue = queue::Queue::open(&data.join("jobs.log"), &data.join("output"), workers)
+        .expect("jobs.log");
+    let queue = Arc::new(queue);
 
-    let api = Router::new()
-        .nest("/bb", bb)
-        .nest("/gh", gh)
-        .layer(middleware::from_fn_with_state(
-            secret.as_bytes().to_vec(),
-            util::signature_middleware,
-        ));
-    let app = Router::new()
+    let bb = bb::create(&bitbucket_username, &bitbucket_password, queue.clone());
+    // Bitbucket Cloud is served only when an app password is configured
+    let bbc = match (
+        std::env::var("BITBUCKET_CLOUD_USERNAME"),
+        std::env::var("BITBUCKET_CLOUD_APP_PASSWORD"),
+    ) {
+        (Ok(username), Ok(password)) => Some(bbc::create(&username, &password, queue.clone())),
+        _ => None,
+    };
+    let gh = gh::create(
+        &git_app_id,
+        git_app_key.as_bytes(),
+        git_hosts,
+        queue.clone(),
+    );
+    // the credentials of every SCM are registered, recovered jobs can run
+    queue.start();
+
+    let mut api = Router::new().nest("/bb", bb).nest("/gh", gh);
+    if let Some(bbc) = bbc {
+        api = api.nest("/bbc", bbc);
+    }
+    let api = api.layer(middleware::from_fn_with_state(
+        secret.as_bytes().to_vec(),
+        util::signature_middleware,
+    ));
+    let mut app = Router::new()
         .nest("/api", api)
-        .route("/ping", get(|| async { "pong" }));
+        .route("/ping", get(|| async { "pong" }))
+        .merge(metrics::create(queue.clone()));
+    // the job API is served only when an admin token is configured
+    match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => app = app.merge(jobs::create(&token, queue.clone())),
+        _ => tracing::warn!("ADMIN_TOKEN is not set, the job API is disabled"),
+    }
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     tracing::debug!("listening on {}", addr);
     axum::Server::bind(&addr)
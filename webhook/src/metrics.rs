@@ -0,0 +1,255 @@
+use crate::queue::{Queue, Status};
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::{routing::get, Router};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+
+// a scan takes seconds on a small pull-request and up to an hour on a huge one
+const DURATION_BUCKETS: &[f64] = &[
+    5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0,
+];
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Metrics of the webhook and the scans. Labels are SCMs, event actions, results,
+/// exit codes and reasons of violations, which are all bounded; repositories and
+/// pull-requests are never labels.
+pub struct Metrics {
+    registry: Registry,
+    pub events: IntCounterVec,
+    pub signature_failures: IntCounter,
+    pub duplicates: IntCounterVec,
+    pub superseded: IntCounterVec,
+    jobs: IntGaugeVec,
+    pub job_duration: HistogramVec,
+    pub exit_codes: IntCounterVec,
+    pub api_errors: IntCounterVec,
+    pub violations: IntCounterVec,
+}
+
+fn counter_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+    counter
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+        let events = counter_vec(
+            &registry,
+            "webhook_events_total",
+            "Events received from the SCMs",
+            &["scm", "action"],
+        );
+        let signature_failures = IntCounter::new(
+            "webhook_signature_failures_total",
+            "Requests whose signature is missing or wrong",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(signature_failures.clone()))
+            .unwrap();
+        let duplicates = counter_vec(
+            &registry,
+            "webhook_duplicated_jobs_total",
+            "Events of a commit which is queued or running already",
+            &["scm"],
+        );
+        let superseded = counter_vec(
+            &registry,
+            "webhook_superseded_jobs_total",
+            "Jobs dropped or killed for a newer commit of the pull-request",
+            &["scm"],
+        );
+        let jobs = IntGaugeVec::new(
+            Opts::new("webhook_jobs", "Jobs which are queued or running"),
+            &["scm", "status"],
+        )
+        .unwrap();
+        registry.register(Box::new(jobs.clone())).unwrap();
+        let job_duration = HistogramVec::new(
+            HistogramOpts::new("webhook_job_duration_seconds", "Time which `work` runs")
+                .buckets(DURATION_BUCKETS.to_vec()),
+            &["scm", "result"],
+        )
+        .unwrap();
+        registry.register(Box::new(job_duration.clone())).unwrap();
+        let exit_codes = counter_vec(
+            &registry,
+            "webhook_work_exits_total",
+            "Exit codes of `work`, none when it was killed or couldn't run",
+            &["scm", "code"],
+        );
+        let api_errors = counter_vec(
+            &registry,
+            "webhook_scm_api_errors_total",
+            "Failed requests to the SCM APIs by the webhook or `work`",
+            &["scm"],
+        );
+        let violations = counter_vec(
+            &registry,
+            "webhook_violations_total",
+            "Violations of the copyright policy which are found",
+            &["scm", "reason"],
+        );
+        Metrics {
+            registry,
+            events,
+            signature_failures,
+            duplicates,
+            superseded,
+            jobs,
+            job_duration,
+            exit_codes,
+            api_errors,
+            violations,
+        }
+    }
+
+    /// Count an event, an action out of `known` is counted as `other`.
+    pub fn event(&self, scm: &str, action: &str, known: &[&str]) {
+        let action = if known.contains(&action) {
+            action
+        } else {
+            "other"
+        };
+        self.events.with_label_values(&[scm, action]).inc();
+    }
+}
+
+async fn render(
+    State(queue): State<Arc<Queue>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let metrics = &*METRICS;
+    // the gauges are taken from the queue, the SCMs without jobs are reported as 0
+    let mut jobs: HashMap<(String, &str), i64> = HashMap::new();
+    for scm in queue.scms() {
+        for status in ["queued", "running"] {
+            jobs.insert((scm.to_string(), status), 0);
+        }
+    }
+    for entry in queue.entries() {
+        let status = match entry.status {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            // a superseded job runs until `work` is killed
+            Status::Superseded if entry.finished_at.is_none() => "running",
+            _ => continue,
+        };
+        *jobs.entry((entry.job.scm, status)).or_default() += 1;
+    }
+    for ((scm, status), count) in jobs {
+        metrics.jobs.with_label_values(&[&scm, status]).set(count);
+    }
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let body = String::from_utf8(buffer)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
+
+/// Metrics in the Prometheus text format.
+pub fn create(queue: Arc<Queue>) -> Router {
+    Router::new()
+        .route("/metrics", get(render))
+        .with_state(queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::{Credentials, EnvsFuture, Job};
+
+    const SCM: &str = "metrics-test";
+
+    /// Credentials which never come, a job stays running.
+    struct Pending;
+
+    impl Credentials for Pending {
+        fn envs<'a>(&'a self, _job: &'a Job) -> EnvsFuture<'a> {
+            Box::pin(std::future::pending())
+        }
+    }
+
+    async fn scrape(queue: &Arc<Queue>) -> String {
+        let response = render(State(queue.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn jobs_are_gauged_by_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue =
+            Queue::open(&dir.path().join("jobs.log"), &dir.path().join("output"), 2).unwrap();
+        let queue = Arc::new(queue);
+        queue.register(SCM, Arc::new(Pending));
+        let text = scrape(&queue).await;
+        assert!(text.contains(r#"webhook_jobs{scm="metrics-test",status="queued"} 0"#));
+        assert!(text.contains(r#"webhook_jobs{scm="metrics-test",status="running"} 0"#));
+        queue.start();
+        // the pull-requests of a repository run one at a time
+        assert!(queue.push(Job::fixture(SCM, "repository", 1, "c0")));
+        assert!(queue.push(Job::fixture(SCM, "repository", 2, "c0")));
+        for _ in 0..100 {
+            if queue.entry(0).map(|x| x.status) == Some(Status::Running) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let text = scrape(&queue).await;
+        assert!(text.contains(r#"webhook_jobs{scm="metrics-test",status="queued"} 1"#));
+        assert!(text.contains(r#"webhook_jobs{scm="metrics-test",status="running"} 1"#));
+        assert!(!queue.push(Job::fixture(SCM, "repository", 2, "c0")));
+        let text = scrape(&queue).await;
+        assert!(text.contains(r#"webhook_duplicated_jobs_total{scm="metrics-test"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn superseded_job_is_running_until_it_exits() {
+        const SCM: &str = "metrics-superseded-test";
+        let dir = tempfile::tempdir().unwrap();
+        let queue =
+            Queue::open(&dir.path().join("jobs.log"), &dir.path().join("output"), 2).unwrap();
+        let queue = Arc::new(queue);
+        queue.register(SCM, Arc::new(Pending));
+        queue.start();
+        assert!(queue.push(Job::fixture(SCM, "repository", 1, "c0")));
+        for _ in 0..100 {
+            if queue.entry(0).map(|x| x.status) == Some(Status::Running) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        // the newer commit waits until the superseded job is gone
+        assert!(queue.push(Job::fixture(SCM, "repository", 1, "c1")));
+        assert_eq!(queue.entry(0).unwrap().status, Status::Superseded);
+        let text = scrape(&queue).await;
+        assert!(text.contains(r#"webhook_jobs{scm="metrics-superseded-test",status="queued"} 1"#));
+        assert!(text.contains(r#"webhook_jobs{scm="metrics-superseded-test",status="running"} 1"#));
+    }
+
+    #[test]
+    fn unknown_actions_are_counted_as_other() {
+        let known = ["opened", "synchronize"];
+        METRICS.event(SCM, "opened", &known);
+        METRICS.event(SCM, "labeled", &known);
+        METRICS.event(SCM, "edited", &known);
+        let count = |action: &str| METRICS.events.with_label_values(&[SCM, action]).get();
+        assert_eq!(
+            (count("opened"), count("synchronize"), count("other")),
+            (1, 0, 2)
+        );
+        assert_eq!(count("labeled"), 0);
+    }
+}
@@ -0,0 +1,848 @@
+use crate::metrics::METRICS;
+use crate::util::{self, Output};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{self, Instant};
+use tokio::sync::Notify;
+
+// finished jobs which are kept with their output
+const HISTORY_LIMIT: usize = 200;
+
+pub type Envs = Vec<(&'static str, String)>;
+pub type EnvsFuture<'a> = Pin<Box<dyn Future<Output = Result<Envs, String>> + Send + 'a>>;
+
+/// Credentials and endpoints of an SCM for `work`. They're resolved when a job starts,
+/// so no secret is written to the queue and a recovered job gets fresh ones.
+pub trait Credentials: Send + Sync {
+    fn envs<'a>(&'a self, job: &'a Job) -> EnvsFuture<'a>;
+}
+
+/// A scan of a pull-request by `work`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub scm: String,
+    pub project: String,
+    pub repository: String,
+    pub id: u32,
+    pub commit: String,
+    /// GitHub instance and the installation of the app on the repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installation: Option<i64>,
+}
+
+impl Job {
+    /// Jobs of the same repository never run at the same time.
+    fn repository_key(&self) -> (String, String, String) {
+        (
+            self.scm.to_string(),
+            self.project.to_string(),
+            self.repository.to_string(),
+        )
+    }
+
+    fn is_same_pull_request(&self, other: &Job) -> bool {
+        self.repository_key() == other.repository_key() && self.id == other.id
+    }
+
+    fn is_same_commit(&self, other: &Job) -> bool {
+        self.is_same_pull_request(other) && self.commit == other.commit
+    }
+
+    /// Job of a pull-request for the tests, each module queues jobs of a `scm` of its own.
+    #[cfg(test)]
+    pub fn fixture(scm: &str, repository: &str, id: u32, commit: &str) -> Job {
+        Job {
+            scm: scm.to_string(),
+            project: "project".to_string(),
+            repository: repository.to_string(),
+            id,
+            commit: commit.to_string(),
+            host: None,
+            installation: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Queued,
+    Running,
+    Finished,
+    Superseded,
+}
+
+/// A job and its progress, times are seconds since the UNIX epoch.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub seq: u64,
+    pub job: Job,
+    pub status: Status,
+    pub queued_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    /// Exit code of `work`, none when it was killed or couldn't run
+    pub code: Option<i32>,
+}
+
+impl Entry {
+    fn is_done(&self) -> bool {
+        self.finished_at.is_some()
+    }
+
+    /// Outcome of a finished job by the exit code of `work`.
+    pub fn result(&self) -> Option<&'static str> {
+        match (self.status, self.code) {
+            (Status::Queued | Status::Running, _) => None,
+            (Status::Superseded, _) => Some("superseded"),
+            (Status::Finished, Some(0)) => Some("passed"),
+            (Status::Finished, Some(1)) => Some("violations"),
+            (Status::Finished, Some(2)) => Some("error"),
+            (Status::Finished, Some(3)) => Some("api-error"),
+            (Status::Finished, _) => Some("aborted"),
+        }
+    }
+}
+
+/// Findings of the JSON report of `work`, only the reason is counted.
+#[derive(Debug, Deserialize)]
+struct Report {
+    findings: Vec<Finding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Finding {
+    reason: String,
+}
+
+/// A line of the queue file. A job is pending until it's finished or superseded by a
+/// newer commit, one which was started but not finished was interrupted and runs again.
+/// The time is missing in the records of older versions.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Record {
+    Queued {
+        seq: u64,
+        job: Job,
+        #[serde(default)]
+        time: u64,
+    },
+    Started {
+        seq: u64,
+        #[serde(default)]
+        time: u64,
+    },
+    Finished {
+        seq: u64,
+        code: Option<i32>,
+        #[serde(default)]
+        time: u64,
+    },
+    Superseded {
+        seq: u64,
+        #[serde(default)]
+        time: u64,
+    },
+}
+
+struct Running {
+    job: Job,
+    superseded: bool,
+    // `work` is killed when it's notified
+    cancel: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct State {
+    next_seq: u64,
+    pending: VecDeque<(u64, Job)>,
+    running: HashMap<u64, Running>,
+    // pending, running and the latest finished jobs
+    entries: BTreeMap<u64, Entry>,
+}
+
+impl State {
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Queued { seq, job, time } => {
+                self.next_seq = self.next_seq.max(seq + 1);
+                self.pending.push_back((seq, job.clone()));
+                self.entries.insert(
+                    seq,
+                    Entry {
+                        seq,
+                        job,
+                        status: Status::Queued,
+                        queued_at: time,
+                        started_at: None,
+                        finished_at: None,
+                        code: None,
+                    },
+                );
+            }
+            Record::Started { seq, time } => {
+                if let Some(entry) = self.entries.get_mut(&seq) {
+                    entry.status = Status::Running;
+                    entry.started_at = Some(time);
+                }
+            }
+            Record::Finished { seq, code, time } => {
+                self.pending.retain(|(x, _)| *x != seq);
+                if let Some(entry) = self.entries.get_mut(&seq) {
+                    if entry.status != Status::Superseded {
+                        entry.status = Status::Finished;
+                    }
+                    entry.finished_at = Some(time);
+                    entry.code = code;
+                }
+            }
+            Record::Superseded { seq, time } => {
+                self.pending.retain(|(x, _)| *x != seq);
+                if let Some(entry) = self.entries.get_mut(&seq) {
+                    entry.status = Status::Superseded;
+                    // a running job is finished when `work` is killed
+                    if entry.started_at.is_none() {
+                        entry.finished_at = Some(time);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records which restore the entries, pending jobs stay in their order.
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        for entry in self.entries.values().filter(|x| x.is_done()) {
+            let seq = entry.seq;
+            let time = entry.finished_at.unwrap_or_default();
+            records.push(Record::Queued {
+                seq,
+                job: entry.job.clone(),
+                time: entry.queued_at,
+            });
+            if let Some(started_at) = entry.started_at {
+                records.push(Record::Started {
+                    seq,
+                    time: started_at,
+                });
+            }
+            if entry.status == Status::Superseded {
+                records.push(Record::Superseded { seq, time });
+            }
+            if entry.started_at.is_some() {
+                records.push(Record::Finished {
+                    seq,
+                    code: entry.code,
+                    time,
+                });
+            }
+        }
+        for (seq, job) in self.pending.iter() {
+            records.push(Record::Queued {
+                seq: *seq,
+                job: job.clone(),
+                time: self.entries[seq].queued_at,
+            });
+        }
+        records
+    }
+
+    /// Drop the oldest finished jobs over the limit, their seqs are handed back.
+    fn trim(&mut self) -> Vec<u64> {
+        let done: Vec<u64> = self
+            .entries
+            .values()
+            .filter(|x| x.is_done())
+            .map(|x| x.seq)
+            .collect();
+        let over = done.len().saturating_sub(HISTORY_LIMIT);
+        for seq in done.iter().take(over) {
+            self.entries.remove(seq);
+        }
+        done.into_iter().take(over).collect()
+    }
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Jobs persisted in an append-only file and run by a fixed number of workers.
+/// The output of `work` is kept in a directory as `{seq}.stdout`, `{seq}.stderr`
+/// and its report as `{seq}.json`.
+pub struct Queue {
+    output: PathBuf,
+    workers: usize,
+    state: Mutex<State>,
+    notify: Notify,
+    providers: RwLock<HashMap<String, Arc<dyn Credentials>>>,
+    // lines of the queue file, they're written by a thread of their own
+    journal: Option<mpsc::Sender<String>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+/// Append the lines to the file. Lines which come while the disk is busy are synced
+/// together, requests don't wait for it.
+fn write_journal(path: PathBuf, receiver: mpsc::Receiver<String>) {
+    while let Ok(line) = receiver.recv() {
+        let lines: Vec<String> = std::iter::once(line).chain(receiver.try_iter()).collect();
+        let ret = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| {
+                for line in lines.iter() {
+                    writeln!(file, "{}", line)?;
+                }
+                file.sync_data()
+            });
+        if let Err(e) = ret {
+            tracing::error!("cannot write job queue {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Queue {
+    /// Load the jobs from the file, it's rewritten with the ones which aren't finished
+    /// and the latest finished ones only.
+    pub fn open(path: &Path, output: &Path, workers: usize) -> std::io::Result<Queue> {
+        let mut state = State::default();
+        if path.is_file() {
+            let content = std::fs::read_to_string(path)?;
+            for line in content.lines().filter(|x| !x.trim().is_empty()) {
+                // the last line may be cut by a crash
+                match serde_json::from_str(line) {
+                    Ok(record) => state.apply(record),
+                    Err(e) => tracing::warn!("skip record of job queue: {}", e),
+                }
+            }
+        }
+        let pending: HashSet<u64> = state.pending.iter().map(|(seq, _)| *seq).collect();
+        let mut interrupted = 0;
+        for entry in state.entries.values_mut().filter(|x| !x.is_done()) {
+            if pending.contains(&entry.seq) {
+                if entry.started_at.take().is_some() {
+                    entry.status = Status::Queued;
+                    interrupted += 1;
+                }
+            } else {
+                // superseded while it was running, but it wasn't recorded as killed
+                entry.finished_at = entry.started_at;
+            }
+        }
+        tracing::info!(
+            "recover {} job(s), {} of them were interrupted",
+            state.pending.len(),
+            interrupted
+        );
+        std::fs::create_dir_all(output)?;
+        let mut queue = Queue {
+            output: output.to_path_buf(),
+            workers: workers.max(1),
+            state: Mutex::new(State::default()),
+            notify: Notify::new(),
+            providers: RwLock::new(HashMap::new()),
+            journal: None,
+            writer: None,
+        };
+        queue.remove_output(&state.trim());
+        let mut compacted = String::new();
+        for record in state.records() {
+            compacted.push_str(&serde_json::to_string(&record)?);
+            compacted.push('\n');
+        }
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, compacted)?;
+        std::fs::rename(&temporary, path)?;
+        *queue.state.lock().unwrap() = state;
+        let (sender, receiver) = mpsc::channel();
+        let path = path.to_path_buf();
+        queue.journal = Some(sender);
+        queue.writer = Some(thread::spawn(move || write_journal(path, receiver)));
+        Ok(queue)
+    }
+
+    /// Credentials of the SCM which is named by `Job::scm`.
+    pub fn register(&self, scm: &str, provider: Arc<dyn Credentials>) {
+        self.providers
+            .write()
+            .unwrap()
+            .insert(scm.to_string(), provider);
+    }
+
+    /// SCMs whose credentials are registered.
+    pub fn scms(&self) -> Vec<String> {
+        self.providers.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Queue the job, false is returned when the commit is queued or running already.
+    /// Jobs of older commits of the pull-request are superseded, the result of the
+    /// pull-request is always the one of its latest commit.
+    pub fn push(&self, job: Job) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let duplicated = state.pending.iter().any(|(_, x)| x.is_same_commit(&job))
+            || state
+                .running
+                .values()
+                .any(|x| !x.superseded && x.job.is_same_commit(&job));
+        if duplicated {
+            METRICS.duplicates.with_label_values(&[&job.scm]).inc();
+            return false;
+        }
+        let seq = state.next_seq;
+        let time = now();
+        let mut records = Vec::new();
+        for (old, running) in state.running.iter_mut() {
+            if running.superseded || !running.job.is_same_pull_request(&job) {
+                continue;
+            }
+            tracing::info!("job {} is superseded by {:?}, kill it", old, job);
+            records.push(Record::Superseded { seq: *old, time });
+            METRICS.superseded.with_label_values(&[&job.scm]).inc();
+            running.superseded = true;
+            running.cancel.notify_one();
+        }
+        // the newer commit is queued at the end like it's replayed from the file
+        let stale = state
+            .pending
+            .iter()
+            .find(|(_, x)| x.is_same_pull_request(&job))
+            .map(|(seq, _)| *seq);
+        if let Some(old) = stale {
+            tracing::info!("job {} is superseded before it started", old);
+            records.push(Record::Superseded { seq: old, time });
+            METRICS.superseded.with_label_values(&[&job.scm]).inc();
+        }
+        records.push(Record::Queued {
+            seq,
+            job: job.clone(),
+            time,
+        });
+        for record in records {
+            self.append(&record);
+            state.apply(record);
+        }
+        let removed = state.trim();
+        drop(state);
+        self.remove_output(&removed);
+        self.notify.notify_waiters();
+        true
+    }
+
+    /// Jobs which are known, the latest first.
+    pub fn entries(&self) -> Vec<Entry> {
+        let state = self.state.lock().unwrap();
+        state.entries.values().rev().cloned().collect()
+    }
+
+    pub fn entry(&self, seq: u64) -> Option<Entry> {
+        self.state.lock().unwrap().entries.get(&seq).cloned()
+    }
+
+    /// File of the `stdout` or `stderr` of the job.
+    pub fn output_path(&self, seq: u64, stream: &str) -> PathBuf {
+        self.output.join(format!("{}.{}", seq, stream))
+    }
+
+    fn remove_output(&self, seqs: &[u64]) {
+        for seq in seqs {
+            for stream in ["stdout", "stderr", "json"] {
+                let _ = std::fs::remove_file(self.output_path(*seq, stream));
+            }
+        }
+    }
+
+    /// Start the workers, the recovered jobs run first.
+    pub fn start(self: &Arc<Self>) {
+        tracing::info!("start {} worker(s) of job queue", self.workers);
+        for _ in 0..self.workers {
+            let queue = self.clone();
+            tokio::spawn(async move { queue.work().await });
+        }
+    }
+
+    async fn work(&self) {
+        loop {
+            // registered before the state is checked, so a push in between isn't missed
+            let notified = self.notify.notified();
+            match self.take() {
+                Some((seq, job, cancel)) => {
+                    let started = Instant::now();
+                    let code = self.run(seq, &job, &cancel).await;
+                    self.finish(seq, code);
+                    self.observe(seq, &job, code, started.elapsed().as_secs_f64());
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// The oldest pending job whose repository has no running job.
+    fn take(&self) -> Option<(u64, Job, Arc<Notify>)> {
+        let mut state = self.state.lock().unwrap();
+        let busy: HashSet<(String, String, String)> = state
+            .running
+            .values()
+            .map(|x| x.job.repository_key())
+            .collect();
+        let idx = state
+            .pending
+            .iter()
+            .position(|(_, x)| !busy.contains(&x.repository_key()))?;
+        let (seq, job) = state.pending.remove(idx)?;
+        let record = Record::Started { seq, time: now() };
+        self.append(&record);
+        state.apply(record);
+        let cancel = Arc::new(Notify::new());
+        state.running.insert(
+            seq,
+            Running {
+                job: job.clone(),
+                superseded: false,
+                cancel: cancel.clone(),
+            },
+        );
+        Some((seq, job, cancel))
+    }
+
+    fn finish(&self, seq: u64, code: Option<i32>) {
+        let mut state = self.state.lock().unwrap();
+        state.running.remove(&seq);
+        let record = Record::Finished {
+            seq,
+            code,
+            time: now(),
+        };
+        self.append(&record);
+        state.apply(record);
+        let removed = state.trim();
+        drop(state);
+        self.remove_output(&removed);
+        // the repository is free for its next job
+        self.notify.notify_waiters();
+    }
+
+    /// Metrics of a finished job, the violations are taken from its report.
+    fn observe(&self, seq: u64, job: &Job, code: Option<i32>, duration: f64) {
+        let result = self
+            .entry(seq)
+            .and_then(|x| x.result())
+            .unwrap_or("aborted");
+        METRICS
+            .job_duration
+            .with_label_values(&[&job.scm, result])
+            .observe(duration);
+        let code = code.map_or("none".to_string(), |x| x.to_string());
+        METRICS
+            .exit_codes
+            .with_label_values(&[&job.scm, &code])
+            .inc();
+        if result == "api-error" {
+            METRICS.api_errors.with_label_values(&[&job.scm]).inc();
+        }
+        let report = match std::fs::read_to_string(self.output_path(seq, "json")) {
+            Ok(x) => x,
+            // `work` failed before the report is written
+            Err(_) => return,
+        };
+        match serde_json::from_str::<Report>(&report) {
+            Ok(report) => {
+                for finding in report.findings.iter() {
+                    METRICS
+                        .violations
+                        .with_label_values(&[&job.scm, &finding.reason])
+                        .inc();
+                }
+            }
+            Err(e) => tracing::error!("cannot read report of job {}: {}", seq, e),
+        }
+    }
+
+    fn output_file(&self, seq: u64, stream: &str) -> Stdio {
+        let path = self.output_path(seq, stream);
+        match std::fs::File::create(&path) {
+            Ok(file) => file.into(),
+            Err(e) => {
+                tracing::error!("cannot create {}: {}", path.display(), e);
+                Stdio::null()
+            }
+        }
+    }
+
+    async fn run(&self, seq: u64, job: &Job, cancel: &Notify) -> Option<i32> {
+        let provider = self.providers.read().unwrap().get(&job.scm).cloned();
+        let provider = match provider {
+            Some(x) => x,
+            None => {
+                tracing::error!("{} is not configured, drop job {:?}", job.scm, job);
+                return None;
+            }
+        };
+        match provider.envs(job).await {
+            Ok(envs) => {
+                let dir = self.output_path(seq, "work");
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    tracing::error!("cannot create {}: {}", dir.display(), e);
+                    return None;
+                }
+                let output = Output {
+                    dir: dir.to_path_buf(),
+                    stdout: self.output_file(seq, "stdout"),
+                    stderr: self.output_file(seq, "stderr"),
+                    report: self.output_path(seq, "json"),
+                };
+                let code = util::run_command(job, envs, output, cancel).await;
+                // the workspace of a killed `work` is left
+                if let Err(e) = std::fs::remove_dir_all(&dir) {
+                    tracing::error!("cannot remove {}: {}", dir.display(), e);
+                }
+                code
+            }
+            Err(e) => {
+                // the installation token of a GitHub App is requested here
+                tracing::error!("cannot get credentials of {:?}: {}", job, e);
+                METRICS.api_errors.with_label_values(&[&job.scm]).inc();
+                None
+            }
+        }
+    }
+
+    /// Records are sent under the lock of the state, so they're written in order.
+    fn append(&self, record: &Record) {
+        let line = match serde_json::to_string(record) {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("cannot write job queue: {}", e);
+                return;
+            }
+        };
+        if let Some(journal) = &self.journal {
+            if journal.send(line).is_err() {
+                tracing::error!("writer of job queue is gone");
+            }
+        }
+    }
+}
+
+impl Drop for Queue {
+    /// The records which are sent are written before the queue is gone.
+    fn drop(&mut self) {
+        self.journal.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(repository: &str, id: u32, commit: &str) -> Job {
+        Job::fixture("queue-test", repository, id, commit)
+    }
+
+    fn open(dir: &tempfile::TempDir) -> Queue {
+        Queue::open(&dir.path().join("jobs.log"), &dir.path().join("output"), 2).unwrap()
+    }
+
+    fn pending(queue: &Queue) -> Vec<u64> {
+        let state = queue.state.lock().unwrap();
+        state.pending.iter().map(|(seq, _)| *seq).collect()
+    }
+
+    fn statuses(queue: &Queue) -> Vec<(u64, Status)> {
+        let mut entries: Vec<(u64, Status)> =
+            queue.entries().iter().map(|x| (x.seq, x.status)).collect();
+        entries.reverse();
+        entries
+    }
+
+    #[test]
+    fn journal_is_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = [
+            r#"{"event":"queued","seq":0,"job":{"scm":"queue-test","project":"project","repository":"a","id":1,"commit":"c0"},"time":10}"#,
+            r#"{"event":"started","seq":0,"time":11}"#,
+            r#"{"event":"finished","seq":0,"code":1,"time":12}"#,
+            // written by an older version without times
+            r#"{"event":"queued","seq":1,"job":{"scm":"queue-test","project":"project","repository":"b","id":1,"commit":"c1"}}"#,
+            r#"{"event":"started","seq":1}"#,
+            r#"{"event":"queued","seq":2,"job":{"scm":"queue-test","project":"project","repository":"c","id":1,"commit":"c2"},"time":13}"#,
+            // cut by a crash
+            r#"{"event":"queued","seq":3,"job":{"scm":"#,
+        ];
+        std::fs::write(dir.path().join("jobs.log"), lines.join("\n")).unwrap();
+        let queue = open(&dir);
+        assert_eq!(
+            statuses(&queue),
+            vec![
+                (0, Status::Finished),
+                (1, Status::Queued),
+                (2, Status::Queued),
+            ]
+        );
+        let finished = queue.entry(0).unwrap();
+        assert_eq!(
+            (
+                finished.queued_at,
+                finished.started_at,
+                finished.finished_at
+            ),
+            (10, Some(11), Some(12))
+        );
+        assert_eq!(finished.result(), Some("violations"));
+        // the interrupted job runs again before the one queued after it
+        assert_eq!(queue.entry(1).unwrap().started_at, None);
+        assert_eq!(pending(&queue), vec![1, 2]);
+        assert!(queue.push(job("d", 1, "c3")));
+        assert_eq!(pending(&queue), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn journal_is_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = open(&dir);
+        for i in 0..HISTORY_LIMIT as u32 + 2 {
+            assert!(queue.push(job("a", i, "c")));
+            let (seq, _, _) = queue.take().unwrap();
+            std::fs::write(queue.output_path(seq, "stdout"), "output").unwrap();
+            queue.finish(seq, Some(0));
+        }
+        assert!(queue.push(job("b", 1, "c")));
+        drop(queue);
+        let queue = open(&dir);
+        let entries = queue.entries();
+        assert_eq!(entries.len(), HISTORY_LIMIT + 1);
+        // the oldest finished jobs are dropped with their output
+        assert_eq!(entries.last().unwrap().seq, 2);
+        assert!(!queue.output_path(1, "stdout").exists());
+        assert!(queue.output_path(2, "stdout").exists());
+        assert_eq!(pending(&queue), vec![HISTORY_LIMIT as u64 + 2]);
+        let content = std::fs::read_to_string(dir.path().join("jobs.log")).unwrap();
+        assert_eq!(content.lines().count(), HISTORY_LIMIT * 3 + 1);
+    }
+
+    #[test]
+    fn records_are_written_before_the_queue_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = open(&dir);
+        assert!(queue.push(job("a", 1, "c0")));
+        assert!(queue.push(job("b", 1, "c1")));
+        let (seq, _, _) = queue.take().unwrap();
+        queue.finish(seq, Some(2));
+        drop(queue);
+        let queue = open(&dir);
+        assert_eq!(
+            statuses(&queue),
+            vec![(0, Status::Finished), (1, Status::Queued)]
+        );
+        assert_eq!(queue.entry(0).unwrap().result(), Some("error"));
+        assert_eq!(pending(&queue), vec![1]);
+    }
+
+    #[test]
+    fn jobs_of_a_repository_run_one_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = open(&dir);
+        assert!(queue.push(job("a", 1, "c0")));
+        assert!(queue.push(job("a", 2, "c1")));
+        assert!(queue.push(job("b", 1, "c2")));
+        let (first, _, _) = queue.take().unwrap();
+        assert_eq!(first, 0);
+        // the other pull-request of the repository waits for the first one
+        assert_eq!(queue.take().unwrap().0, 2);
+        assert!(queue.take().is_none());
+        assert_eq!(queue.entry(1).unwrap().status, Status::Queued);
+        queue.finish(first, Some(0));
+        assert_eq!(queue.take().unwrap().0, 1);
+        assert_eq!(
+            statuses(&queue),
+            vec![
+                (0, Status::Finished),
+                (1, Status::Running),
+                (2, Status::Running),
+            ]
+        );
+    }
+
+    #[test]
+    fn commit_is_queued_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = open(&dir);
+        assert!(queue.push(job("a", 1, "c0")));
+        assert!(!queue.push(job("a", 1, "c0")));
+        queue.take().unwrap();
+        // an event of the commit comes again while it's scanned
+        assert!(!queue.push(job("a", 1, "c0")));
+        // the same commit in another pull-request is scanned for it
+        assert!(queue.push(job("a", 2, "c0")));
+        assert_eq!(pending(&queue), vec![1]);
+    }
+
+    #[test]
+    fn newer_commit_supersedes_queued_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = open(&dir);
+        assert!(queue.push(job("a", 1, "c0")));
+        assert!(queue.push(job("b", 1, "c1")));
+        assert!(queue.push(job("a", 1, "c2")));
+        assert_eq!(
+            statuses(&queue),
+            vec![
+                (0, Status::Superseded),
+                (1, Status::Queued),
+                (2, Status::Queued),
+            ]
+        );
+        assert_eq!(queue.entry(0).unwrap().result(), Some("superseded"));
+        // the superseded commit can come again after a force-push back
+        assert!(queue.push(job("a", 1, "c0")));
+        assert_eq!(pending(&queue), vec![1, 3]);
+        drop(queue);
+        // the order in memory is the one which is replayed
+        let queue = open(&dir);
+        assert_eq!(pending(&queue), vec![1, 3]);
+        assert_eq!(queue.entry(2).unwrap().status, Status::Superseded);
+    }
+
+    #[tokio::test]
+    async fn newer_commit_supersedes_running_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = open(&dir);
+        assert!(queue.push(job("a", 1, "c0")));
+        let (seq, _, cancel) = queue.take().unwrap();
+        assert!(queue.push(job("a", 1, "c1")));
+        tokio::time::timeout(time::Duration::from_secs(1), cancel.notified())
+            .await
+            .expect("running job is cancelled");
+        let entry = queue.entry(seq).unwrap();
+        assert_eq!(
+            (entry.status, entry.finished_at),
+            (Status::Superseded, None)
+        );
+        // the newer commit waits until the killed one is gone
+        assert!(queue.take().is_none());
+        queue.finish(seq, None);
+        assert_eq!(queue.entry(seq).unwrap().result(), Some("superseded"));
+        assert_eq!(queue.take().unwrap().0, 1);
+        drop(queue);
+        let queue = open(&dir);
+        assert_eq!(
+            statuses(&queue),
+            vec![(0, Status::Superseded), (1, Status::Queued)]
+        );
+    }
+}
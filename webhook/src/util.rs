@@ -1,52 +1,27 @@
+use crate::metrics::METRICS;
+use crate::queue::{Envs, Job};
 use axum::extract::State;
-use axum::http::{Request, StatusCode};
+use axum::http::{HeaderMap, Request, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
-use tokio::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Notify, OnceCell};
 use std::borrow::Borrow;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hasher;
 use std::path::PathBuf;
-use std::sync::RwLock;
+use std::process::Stdio;
+use std::time::Duration;
 
-static GLOBAL_DATA: RwLock<Vec<u64>> = RwLock::new(Vec::new());
-
-pub fn protect_enter(scm: &str, project: &str, repository: &str, id: u32) -> bool {
-    let key = get_key(scm, id, project, repository);
-    {
-        let found = GLOBAL_DATA.read().unwrap().iter().any(|x| *x == key);
-        // tracing::info!("key is {}, found: {}", key, found);
-        if found {
-            return false;
-        }
-    }
-    {
-        GLOBAL_DATA.write().unwrap().push(key);
-        // tracing::info!("add key is {}", key);
-    }
-    true
-}
-
-pub fn protect_leave(scm: &str, project: &str, repository: &str, id: u32) {
-    let key = get_key(scm, id, project, repository);
-    let mut item = GLOBAL_DATA.write().unwrap();
-    let index = item.iter().position(|x| *x == key);
-    if let Some(idx) = index {
-        item.remove(idx);
-    }
-}
+static WORK_DIR: OnceCell<PathBuf> = OnceCell::const_new();
+// time for `work` to report a cancelled scan and remove its workspace
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(30);
 
-fn get_key(scm: &str, id: u32, project: &str, repository: &str) -> u64 {
-    let key = format!("{}-{}-{}-{}", scm, id, project, repository);
-    let mut s = DefaultHasher::new();
-    s.write(&key.as_bytes());
-    s.finish()
+/// Directory where `work` runs and the job queue is kept.
+pub fn data_dir() -> PathBuf {
+    let path = std::env::current_exe().unwrap();
+    path.parent().unwrap().join("data")
 }
 
-static WORK_DIR: OnceCell<PathBuf> = OnceCell::const_new();
-
 async fn get_work_dir() -> PathBuf {
     let path = std::env::current_exe().unwrap();
     let folder = path.parent().unwrap();
@@ -60,69 +35,161 @@ async fn get_work_dir() -> PathBuf {
     folder.to_path_buf()
 }
 
-pub async fn run_command(
-    username: &str,
-    password: &str,
-    id: u32,
-    project: &str,
-    repository: &str,
-    scm: &str,
-) {
+/// Where `work` runs and where its output goes, the report is written in JSON.
+pub struct Output {
+    /// `work` creates its workspace in it, nothing is left there when it's killed
+    pub dir: PathBuf,
+    pub stdout: Stdio,
+    pub stderr: Stdio,
+    pub report: PathBuf,
+}
+
+/// Send the signal to `work` and the git commands it runs, they're in its process group.
+fn signal_group(pid: u32, signal: libc::c_int) {
+    // SAFETY: kill(2) doesn't touch the memory of this process
+    let ret = unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+    if ret != 0 {
+        tracing::error!("cannot signal work: {}", std::io::Error::last_os_error());
+    }
+}
+
+/// Run `work` on the job, its exit code is handed back. When `cancel` is notified, even
+/// before it's spawned, it's terminated and killed if it doesn't stop in time.
+pub async fn run_command(job: &Job, envs: Envs, output: Output, cancel: &Notify) -> Option<i32> {
     let dir = WORK_DIR.get_or_init(get_work_dir).await;
     
     let mut command = tokio::process::Command::new(
         dir.join("work"),
     );
     command
-        .env("GIT_USERNAME", username)
-        .env("GIT_PASSWORD", password)
         .env(
             "GIT_ASKPASS",
             dir.join("askpass.sh"),
         )
+        .envs(envs)
+        .arg("pr")
         .args([
-            format!("--project={}", project),
-            format!("--repository={}", repository),
-            format!("--id={}", id),
-            format!("--scm={}", scm),
+            format!("--project={}", job.project),
+            format!("--repository={}", job.repository),
+            format!("--id={}", job.id),
+            format!("--scm={}", job.scm),
+            format!("--commit={}", job.commit),
+            "--format=json".to_string(),
+            format!("--output={}", output.report.display()),
         ])
-        .current_dir(dir.join("data"));
-    let mut proc = command.spawn().unwrap();
-    let ret = proc.wait().await;
+        .current_dir(&output.dir)
+        .process_group(0)
+        .stdout(output.stdout)
+        .stderr(output.stderr);
+    let mut proc = match command.spawn() {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::error!("cannot run work: {:?}", e);
+            return None;
+        }
+    };
+    // it's the id of the process group too
+    let pid = proc.id().unwrap_or_default();
+    let ret = tokio::select! {
+        ret = proc.wait() => ret,
+        _ = cancel.notified() => {
+            signal_group(pid, libc::SIGTERM);
+            if tokio::time::timeout(CANCEL_GRACE_PERIOD, proc.wait()).await.is_err() {
+                tracing::warn!("work doesn't stop in time, kill it");
+                signal_group(pid, libc::SIGKILL);
+                let _ = proc.wait().await;
+            }
+            tracing::debug!(
+                "project: {}, repository: {}, id: {} is cancelled",
+                job.project,
+                job.repository,
+                job.id
+            );
+            return None;
+        }
+    };
     match ret {
-        Ok(_) => tracing::debug!(
-            "project: {}, repository: {}, id: {} is completed",
-            project,
-            repository,
-            id
-        ),
-        Err(e) => tracing::debug!(
-            "project: {}, repository: {}, id: {} ran with error {:?}",
-            project,
-            repository,
-            id,
-            e
-        ),
+        Ok(status) => {
+            tracing::debug!(
+                "project: {}, repository: {}, id: {} is completed with {}",
+                job.project,
+                job.repository,
+                job.id,
+                status
+            );
+            status.code()
+        }
+        Err(e) => {
+            tracing::debug!(
+                "project: {}, repository: {}, id: {} ran with error {:?}",
+                job.project,
+                job.repository,
+                job.id,
+                e
+            );
+            None
+        }
     }
-    protect_leave(scm, project, repository, id);
 }
 
+/// Bytes of a hex string in either case, `None` when it isn't one.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            Some((high * 16 + low) as u8)
+        })
+        .collect()
+}
+
+/// The HMAC of the body is compared in constant time.
 fn check_signature(
     signature: &str,
     data: &[u8],
     secret: &[u8],
 ) -> Result<(), (StatusCode, String)> {
+    let signature = decode_hex(signature).ok_or((
+        StatusCode::FORBIDDEN,
+        "signature is wrong format".to_string(),
+    ))?;
     let mut hmac = Hmac::<Sha256>::new_from_slice(secret)
         .map_err(|err| (StatusCode::FORBIDDEN, err.to_string()))?;
     hmac.update(data);
-    let verify = format!("{:x}", hmac.finalize().into_bytes());
-    if verify != signature {
-        return Err((
+    hmac.verify_slice(&signature).map_err(|_| {
+        (
             StatusCode::FORBIDDEN,
             "signature isn't verified".to_string(),
-        ));
-    }
-    Ok(())
+        )
+    })
+}
+
+/// Signature of the body in the headers, it's checked against the secret.
+fn verify_request(
+    headers: &HeaderMap,
+    data: &[u8],
+    secret: &[u8],
+) -> Result<(), (StatusCode, String)> {
+    // Bitbucket Cloud sends the same signature under the older name
+    let hdr = headers
+        .get("X-Hub-Signature-256")
+        .or_else(|| headers.get("X-Hub-Signature"))
+        .ok_or((
+            StatusCode::FORBIDDEN,
+            "'X-Hub-Signature-256': not found".to_string(),
+        ))?;
+    let message = hdr
+        .to_str()
+        .map_err(|e| (StatusCode::FORBIDDEN, e.to_string()))?;
+    let signature = message.strip_prefix("sha256=").ok_or((
+        StatusCode::FORBIDDEN,
+        "signature is wrong format".to_string(),
+    ))?;
+    check_signature(signature, data, secret)
 }
 
 pub async fn signature_middleware(
@@ -137,18 +204,67 @@ pub async fn signature_middleware(
     let bytes = hyper::body::to_bytes(body)
         .await
         .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())?;
-    let hdr = parts
-        .headers
-        .get("X-Hub-Signature-256")
-        .ok_or((StatusCode::FORBIDDEN, "'X-Hub-Signature-256': not found").into_response())?;
-    let message = hdr
-        .to_str()
-        .map_err(|e| (StatusCode::FORBIDDEN, e.to_string()).into_response())?;
-    let signature = message
-        .strip_prefix("sha256=")
-        .ok_or((StatusCode::FORBIDDEN, "signature is wrong format").into_response())?;
-    check_signature(&signature.to_lowercase(), bytes.borrow(), &secret)
-        .map_err(|e| e.into_response())?;
+    if let Err(e) = verify_request(&parts.headers, bytes.borrow(), &secret) {
+        METRICS.signature_failures.inc();
+        return Err(e.into_response());
+    }
     let request = Request::from_parts(parts, hyper::Body::from(bytes));
     Ok(next.run(request).await)
 }
+
+/// Requests need `Authorization: Bearer <token>` with the admin token.
+pub async fn admin_middleware(
+    State(token): State<String>,
+    req: Request<hyper::Body>,
+    next: Next<hyper::Body>,
+) -> Result<impl IntoResponse, Response> {
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "admin token is missing").into_response())?;
+    // digests of a fixed size are compared in constant time, so neither the content
+    // nor the length of the token is leaked
+    let matched = Sha256::digest(bearer.as_bytes())
+        .iter()
+        .zip(Sha256::digest(token.as_bytes()).iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0;
+    if !matched {
+        return Err((StatusCode::FORBIDDEN, "admin token is wrong").into_response());
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const BODY: &[u8] = br#"{"action":"opened"}"#;
+
+    fn sign(data: &[u8]) -> String {
+        let mut hmac = Hmac::<Sha256>::new_from_slice(SECRET).unwrap();
+        hmac.update(data);
+        format!("{:x}", hmac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn signature_is_verified_in_either_case() {
+        let signature = sign(BODY);
+        assert!(check_signature(&signature, BODY, SECRET).is_ok());
+        assert!(check_signature(&signature.to_uppercase(), BODY, SECRET).is_ok());
+    }
+
+    #[test]
+    fn wrong_signature_is_refused() {
+        let signature = sign(b"{}");
+        assert!(check_signature(&signature, BODY, SECRET).is_err());
+        // a prefix of the right one, an odd length and a non-hex digit
+        assert!(check_signature(&sign(BODY)[..32], BODY, SECRET).is_err());
+        assert!(check_signature(&sign(BODY)[1..], BODY, SECRET).is_err());
+        let non_hex = format!("{}g", &sign(BODY)[1..]);
+        assert!(check_signature(&non_hex, BODY, SECRET).is_err());
+    }
+}
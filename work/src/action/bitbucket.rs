@@ -2,25 +2,60 @@ use reqwest::blocking::Client;
 use serde_json::json;
 // use serde::{Serialize, Deserialize};
 use crate::action::models::{
-    BitbucketActivitiesPayload, BitbucketChangesPayload, BitbucketPagePayload,
+    BitbucketActivitiesPayload, BitbucketChangesPayload, BitbucketComment, BitbucketPagePayload,
+    BitbucketPullRequestPayload, BitbucketRef, CommentAnchor,
+};
+use crate::action::{
+    conclude, file_comment_path, git_fetch, is_report_comment, reconcile_file_comments,
+    render_file_comment, render_report_comment, render_summary, scan, ApiError, BuildState, Change,
+    ChangeKind, FileComment, Options, Report, CHECK_NAME,
 };
-use crate::action::{git_fetch, scan};
 use crate::Handler;
 
-use super::models::{BAD_COMMENT, GOOD_COMMENT};
+// Code Insights limits
+const DETAILS_LIMIT: usize = 2000;
+const ANNOTATIONS_LIMIT: usize = 1000;
 
 pub struct Bitbucket {
     client: Client,
+    endpoints: Endpoints,
     base_url: String,
 }
 
+/// URLs of a Bitbucket Server instance.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// the REST APIs are under it, e.g. `https://bitbucket.example.com/rest`
+    pub rest: String,
+    /// `{project}` and `{repository}` are replaced with the repository
+    pub clone: String,
+}
+
+impl Endpoints {
+    /// The clone URL is the HTTP one of the instance when it is omitted.
+    pub fn new(url: &str, rest_path: &str, clone: Option<String>) -> Endpoints {
+        let url = url.trim_end_matches('/');
+        let clone = clone.unwrap_or_else(|| format!("{}/scm/{{project}}/{{repository}}.git", url));
+        Endpoints {
+            rest: format!("{}/{}", url, rest_path.trim_matches('/')),
+            clone,
+        }
+    }
+
+    fn clone_url(&self, project: &str, repository: &str) -> String {
+        self.clone
+            .replace("{project}", project)
+            .replace("{repository}", repository)
+    }
+}
+
 // #[derive(Debug, Clone, Serialize, Deserialize)]
 // struct Comment {
 //     text: String,
 // }
 
 impl Bitbucket {
-    pub fn new(project: &str, repository: &str, id: u32) -> Bitbucket {
+    pub fn new(project: &str, repository: &str, id: u32, endpoints: Endpoints) -> Bitbucket {
         let username = std::env::var("GIT_USERNAME").expect("GIT_USERNAME is not set");
         let password = std::env::var("GIT_PASSWORD").expect("GIT_PASSWORD is not set");
         let auth = format!("{}:{}", username, password);
@@ -42,15 +77,29 @@ impl Bitbucket {
             .default_headers(header)
             .build()
             .unwrap();
+        let base_url = format!(
+            "{}/api/1.0/projects/{}/repos/{}/pull-requests/{}",
+            endpoints.rest, project, repository, id
+        );
         Bitbucket {
             client: client.to_owned(),
-            base_url: format!("https://code-dev.do.citrite.net/rest/api/1.0/projects/{}/repos/{}/pull-requests/{}", &project, &repository, id),
+            endpoints,
+            base_url,
         }
     }
 
-    fn get_changed_files(&self) -> anyhow::Result<Vec<String>> {
+    fn get_pull_request(&self) -> anyhow::Result<BitbucketPullRequestPayload> {
+        let resp = self.client.get(&self.base_url).send()?;
+        if resp.status().is_success() {
+            Ok(resp.json()?)
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    fn get_changed_files(&self) -> anyhow::Result<Vec<Change>> {
         let mut start = 0;
-        let mut files: Vec<String> = vec![];
+        let mut files: Vec<Change> = vec![];
         loop {
             let url = format!("{}/changes?start={}", &self.base_url, start);
             let resp = self.client.get(&url).send()?;
@@ -58,7 +107,21 @@ impl Bitbucket {
                 let payload: BitbucketPagePayload = resp.json()?;
                 let changes: Vec<BitbucketChangesPayload> = serde_json::from_value(payload.values)?;
                 for value in changes.iter() {
-                    files.push(String::from(&value.path.to_string));
+                    let kind = match value.type_field.as_deref() {
+                        Some("ADD") | Some("COPY") => ChangeKind::Added,
+                        Some("DELETE") => ChangeKind::Deleted,
+                        Some("MOVE") => ChangeKind::Renamed,
+                        _ => ChangeKind::Modified,
+                    };
+                    files.push(Change {
+                        from: match kind {
+                            ChangeKind::Renamed => {
+                                value.src_path.as_ref().map(|x| x.to_string.to_string())
+                            }
+                            _ => None,
+                        },
+                        ..Change::new(&value.path.to_string, kind)
+                    });
                 }
                 if payload.is_last_page {
                     return Ok(files);
@@ -69,13 +132,15 @@ impl Bitbucket {
                     return Ok(files);
                 }
             } else {
-                return Err(anyhow::anyhow!(resp.status().to_string()));
+                return Err(ApiError(resp.status().to_string()).into());
             }
         }
     }
 
-    fn get_comment(&self) -> anyhow::Result<Option<(i32, i32)>> {
+    /// Comments of the pull-request, with the anchor of inline comments.
+    fn get_comments(&self) -> anyhow::Result<Vec<(BitbucketComment, Option<CommentAnchor>)>> {
         let mut start = 0;
+        let mut comments: Vec<(BitbucketComment, Option<CommentAnchor>)> = vec![];
         loop {
             let url = format!("{}/activities?start={}", self.base_url, start);
             let resp = self.client.get(&url).send()?;
@@ -83,10 +148,11 @@ impl Bitbucket {
                 let payload: BitbucketPagePayload = resp.json()?;
                 let activities: Vec<BitbucketActivitiesPayload> =
                     serde_json::from_value(payload.values)?;
-                for value in activities.iter() {
-                    if let Some(comment) = &value.comment {
-                        if comment.text.ends_with("reported by CICD") {
-                            return Ok(Some((comment.id, comment.version)));
+                for value in activities.into_iter() {
+                    // an edited comment shows up in several activities
+                    if let Some(comment) = value.comment {
+                        if !comments.iter().any(|x| x.0.id == comment.id) {
+                            comments.push((comment, value.comment_anchor));
                         }
                     }
                 }
@@ -99,60 +165,373 @@ impl Bitbucket {
                     break;
                 }
             } else {
-                return Err(anyhow::anyhow!(resp.status().to_string()));
+                return Err(ApiError(resp.status().to_string()).into());
             }
         }
-        Ok(None)
+        Ok(comments)
     }
 
-    fn delete_comment(&self, id: i32, version: i32) -> anyhow::Result<()> {
-        let url = format!(
-            "{baseUrl}/comments/{id}?version={version}",
-            baseUrl = self.base_url,
-            id = id,
-            version = version
-        );
-        let resp = self.client.delete(&url).send()?;
+    /// The comment which reports the scans, with its text and version.
+    fn get_report_comment(&self) -> anyhow::Result<Option<BitbucketComment>> {
+        let comment = self
+            .get_comments()?
+            .into_iter()
+            .map(|(x, _)| x)
+            .find(|x| is_report_comment(&x.text));
+        Ok(comment)
+    }
+
+    fn create_comment(&self, message: &str) -> anyhow::Result<()> {
+        let url = format!("{}/comments", self.base_url);
+        let body = json!({ "text": message });
+        let resp = self.client.post(&url).json(&body).send()?;
         if resp.status().is_success() {
             Ok(())
         } else {
-            return Err(anyhow::anyhow!(resp.status().to_string()));
+            Err(ApiError(resp.status().to_string()).into())
         }
     }
 
-    fn create_comment(&self, positive: bool) -> anyhow::Result<()> {
-        let url = format!("{baseUrl}/comments", baseUrl = self.base_url);
-        let message = if positive { BAD_COMMENT } else { GOOD_COMMENT };
-        let body = json!({ "text": message });
-        //let resp = self.client.post(&url).json(&Comment{text: message.to_string()}).send()?;
+    fn update_comment(&self, comment: &BitbucketComment, message: &str) -> anyhow::Result<()> {
+        let url = format!("{}/comments/{}", self.base_url, comment.id);
+        let body = json!({ "text": message, "version": comment.version });
+        let resp = self.client.put(&url).json(&body).send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    fn resolve_comment(&self, id: i32, version: i32) -> anyhow::Result<()> {
+        let url = format!("{}/comments/{}", self.base_url, id);
+        let body = json!({ "version": version, "state": "RESOLVED" });
+        let resp = self.client.put(&url).json(&body).send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    /// Comment on every offending file, the comments of earlier runs are reconciled with
+    /// the report. An orphaned comment is an outdated one.
+    fn review(&self, files: &[Change], report: &Report) -> anyhow::Result<()> {
+        let mut comments: Vec<FileComment<(i32, i32)>> = vec![];
+        for (comment, anchor) in self.get_comments()? {
+            if let Some(path) = file_comment_path(&comment.text) {
+                comments.push(FileComment {
+                    id: (comment.id, comment.version),
+                    path: path.to_string(),
+                    open: comment.state == "OPEN",
+                    outdated: anchor.is_some_and(|x| x.orphaned),
+                });
+            }
+        }
+        let (stale, new) = reconcile_file_comments(comments, files, report);
+        for comment in stale {
+            tracing::info!("resolve comment on {}", comment.path);
+            self.resolve_comment(comment.id.0, comment.id.1)?;
+        }
+        let url = format!("{}/comments", self.base_url);
+        for comment in new {
+            let finding = comment.finding;
+            let body = match comment.line {
+                Some(line) => json!({
+                    "text": render_file_comment(finding, true),
+                    "anchor": {
+                        "path": finding.path,
+                        "line": line,
+                        "lineType": "ADDED",
+                        "fileType": "TO",
+                        "diffType": "EFFECTIVE",
+                    },
+                }),
+                None => json!({
+                    "text": render_file_comment(finding, false),
+                    "anchor": { "path": finding.path, "diffType": "EFFECTIVE" },
+                }),
+            };
+            tracing::info!("comment on {}", finding.path);
+            let resp = self.client.post(&url).json(&body).send()?;
+            if !resp.status().is_success() {
+                return Err(ApiError(resp.status().to_string()).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the build status of the scan on the commit, `url` is where the status links to.
+    fn set_status(
+        &self,
+        commit: &str,
+        url: &str,
+        state: BuildState,
+        description: &str,
+    ) -> anyhow::Result<()> {
+        let state = match state {
+            BuildState::InProgress => "INPROGRESS",
+            BuildState::Successful => "SUCCESSFUL",
+            BuildState::Failed => "FAILED",
+            // the build status of Bitbucket Server has no cancelled state
+            BuildState::Cancelled => "FAILED",
+        };
+        let body = json!({
+            "state": state,
+            "key": CHECK_NAME,
+            "name": "Copyright",
+            "url": url,
+            "description": description,
+        });
+        let resp = self
+            .client
+            .post(format!(
+                "{}/build-status/1.0/commits/{}",
+                self.endpoints.rest, commit
+            ))
+            .json(&body)
+            .send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    /// Publish the Code Insights report of the scan on the commit, the annotations
+    /// of an earlier run on the same commit are replaced. A merge check can require it.
+    fn publish_report(
+        &self,
+        from: &BitbucketRef,
+        commit: &str,
+        result: &anyhow::Result<Report>,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/insights/1.0/projects/{}/repos/{}/commits/{}/reports/{}",
+            self.endpoints.rest,
+            from.repository.project.key,
+            from.repository.slug,
+            commit,
+            CHECK_NAME
+        );
+        let body = match result {
+            Ok(report) => {
+                let passed = report.findings.is_empty();
+                json!({
+                    "title": "Copyright",
+                    "details": truncate(&render_summary(report), DETAILS_LIMIT),
+                    "result": if passed { "PASS" } else { "FAIL" },
+                    "reporter": "copyright-rust",
+                    "data": [
+                        {
+                            "title": "Files scanned",
+                            "type": "NUMBER",
+                            "value": report.scanned.len(),
+                        },
+                        {
+                            "title": "Violations",
+                            "type": "NUMBER",
+                            "value": report.findings.len(),
+                        },
+                    ],
+                })
+            }
+            Err(err) => {
+                let details = format!("copyright check failed to run: {:#}", err);
+                json!({
+                    "title": "Copyright",
+                    "details": truncate(&details, DETAILS_LIMIT),
+                    "result": "FAIL",
+                    "reporter": "copyright-rust",
+                })
+            }
+        };
+        let resp = self.client.put(&url).json(&body).send()?;
+        if !resp.status().is_success() {
+            return Err(ApiError(resp.status().to_string()).into());
+        }
+        let url = format!("{}/annotations", url);
+        let resp = self.client.delete(&url).send()?;
+        if !resp.status().is_success() {
+            return Err(ApiError(resp.status().to_string()).into());
+        }
+        let findings = match result {
+            Ok(report) if !report.findings.is_empty() => &report.findings,
+            _ => return Ok(()),
+        };
+        let annotations: Vec<serde_json::Value> = findings
+            .iter()
+            .take(ANNOTATIONS_LIMIT)
+            .map(|x| {
+                json!({
+                    "externalId": format!("{}:{}", CHECK_NAME, x.path),
+                    "path": x.path,
+                    "line": x.lines.0,
+                    "message": x.reason.to_string(),
+                    "severity": "HIGH",
+                    "type": "CODE_SMELL",
+                })
+            })
+            .collect();
+        let body = json!({ "annotations": annotations });
         let resp = self.client.post(&url).json(&body).send()?;
         if resp.status().is_success() {
             Ok(())
         } else {
-            Err(anyhow::anyhow!(resp.status().to_string()))
+            Err(ApiError(resp.status().to_string()).into())
         }
     }
 }
 
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((idx, _)) => text[..idx].to_string(),
+        None => text.to_string(),
+    }
+}
+
 impl Handler for Bitbucket {
-    fn execute(&mut self, project: &str, repository: &str, id: u32) -> anyhow::Result<()> {
+    fn execute(
+        &mut self,
+        project: &str,
+        repository: &str,
+        id: u32,
+        options: &Options,
+    ) -> anyhow::Result<Report> {
+        let pull_request = self.get_pull_request()?;
         let files = self.get_changed_files()?;
-        git_fetch(
+        let from = &pull_request.from_ref;
+        let commit = options.commit.as_deref().unwrap_or(&from.latest_commit);
+        // the build status requires a link, it points to the pull-request
+        let link = match pull_request.links.self_field.first() {
+            Some(x) => x.href.to_string(),
+            None => self.base_url.to_string(),
+        };
+        self.set_status(
+            commit,
+            &link,
+            BuildState::InProgress,
+            "copyright check is running",
+        )?;
+        let result = git_fetch(
             &files,
-            &format!(
-                "https://code-dev.do.citrite.net/scm/{project}/{repo}.git",
-                project = project,
-                repo = repository
-            ),
+            &self.endpoints.clone_url(project, repository),
+            &format!("refs/pull/{}/head", id),
             id,
-        )?;
-        let yes = scan()?;
-        let comment_opt = self.get_comment()?;
-        if let Some(comment) = comment_opt {
-            self.delete_comment(comment.0, comment.1)?;
+        )
+        .and_then(|_| {
+            let same_repository = from.repository.slug == pull_request.to_ref.repository.slug
+                && from.repository.project.key == pull_request.to_ref.repository.project.key;
+            let branch = match options.fix {
+                true if !same_repository => {
+                    tracing::warn!("cannot push fixes to a pull-request from fork");
+                    None
+                }
+                true => Some(from.display_id.as_str()),
+                false => None,
+            };
+            scan(&files, branch)
+        });
+        // the build status is concluded before the report, which may fail on its own
+        let (state, description) = conclude(&result);
+        self.set_status(commit, &link, state, &description)?;
+        tracing::info!("publish code insights report on {}", commit);
+        self.publish_report(from, commit, &result)?;
+        let report = result?;
+        self.review(&files, &report)?;
+        let comment = self.get_report_comment()?;
+        let previous = comment.as_ref().map(|x| x.text.as_str());
+        let message = render_report_comment(&report, commit, previous);
+        match &comment {
+            Some(comment) => {
+                tracing::info!("update comment in pull-request");
+                self.update_comment(comment, &message)?;
+            }
+            None if !report.findings.is_empty() => {
+                tracing::info!("report comment to pull-request");
+                self.create_comment(&message)?;
+            }
+            None => tracing::info!("nothing to report to pull-request"),
         }
-        tracing::info!("create pull-request for scanned result: {}", yes);
-        self.create_comment(yes)?;
-        Ok(())
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::fake;
+    use axum::extract::Query;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    fn path(path: &str) -> Value {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        json!({
+            "components": path.split('/').collect::<Vec<_>>(),
+            "parent": parent,
+            "name": name,
+            "extension": name.rsplit_once('.').map(|x| x.1).unwrap_or_default(),
+            "toString": path,
+        })
+    }
+
+    fn change(kind: &str, to: &str, from: Option<&str>) -> Value {
+        json!({"type": kind, "path": path(to), "srcPath": from.map(path)})
+    }
+
+    /// Changes of the fake pull-request, which come in two pages.
+    async fn changes(
+        headers: HeaderMap,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let basic = headers.get("Authorization").and_then(|x| x.to_str().ok());
+        if basic != Some(&format!("Basic {}", base64::encode("fake:secret"))) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        let page = match query.get("start").map(|x| x.as_str()) {
+            Some("0") => json!({
+                "size": 1, "limit": 1, "start": 0, "isLastPage": false, "nextPageStart": 1,
+                "values": [change("ADD", "src/a.rs", None)],
+            }),
+            _ => json!({
+                "size": 2, "limit": 2, "start": 1, "isLastPage": true,
+                "values": [
+                    change("MOVE", "src/c.rs", Some("src/b.rs")),
+                    change("DELETE", "src/d.rs", None),
+                ],
+            }),
+        };
+        Ok(Json(page))
+    }
+
+    #[test]
+    fn changes_are_read_from_configured_server() {
+        fake::credentials();
+        let url = fake::serve(Router::new().route(
+            "/bitbucket/rest/api/1.0/projects/PRJ/repos/repo/pull-requests/1/changes",
+            get(changes),
+        ));
+        let url = format!("{}/bitbucket", url);
+        let bitbucket = Bitbucket::new("PRJ", "repo", 1, Endpoints::new(&url, "/rest/", None));
+        let files = bitbucket.get_changed_files().unwrap();
+        assert_eq!(
+            files,
+            vec![
+                Change::new("src/a.rs", ChangeKind::Added),
+                Change {
+                    from: Some("src/b.rs".to_string()),
+                    ..Change::new("src/c.rs", ChangeKind::Renamed)
+                },
+                Change::new("src/d.rs", ChangeKind::Deleted),
+            ]
+        );
+        assert_eq!(
+            bitbucket.endpoints.clone_url("PRJ", "repo"),
+            format!("{}/scm/PRJ/repo.git", url)
+        );
     }
 }
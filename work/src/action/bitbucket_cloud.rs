@@ -0,0 +1,385 @@
+use crate::action::models::{
+    CloudComment, CloudDiffstatPayload, CloudPagePayload, CloudPullRequestPayload,
+};
+use crate::action::{
+    conclude, file_comment_path, git_fetch, is_report_comment, reconcile_file_comments,
+    render_file_comment, render_report_comment, scan, ApiError, BuildState, Change, ChangeKind,
+    FileComment, Handler, Options, Report, CHECK_NAME,
+};
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+// the largest page the 2.0 API hands out for diffstat and comments
+const PAGE_LENGTH: u32 = 100;
+
+pub struct BitbucketCloud {
+    client: Client,
+    endpoints: Endpoints,
+    base_url: String,
+}
+
+/// URLs of Bitbucket Cloud.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// the 2.0 API, e.g. `https://api.bitbucket.org/2.0`
+    pub api: String,
+    /// `{project}` and `{repository}` are replaced with the workspace and the repository
+    pub clone: String,
+}
+
+impl Endpoints {
+    pub fn new(api: &str, clone: &str) -> Endpoints {
+        Endpoints {
+            api: api.trim_end_matches('/').to_string(),
+            clone: clone.to_string(),
+        }
+    }
+
+    /// `full_name` is `{workspace}/{slug}` of a repository.
+    fn clone_url(&self, full_name: &str) -> String {
+        let (workspace, slug) = full_name.split_once('/').unwrap_or(("", full_name));
+        self.clone
+            .replace("{project}", workspace)
+            .replace("{repository}", slug)
+    }
+}
+
+impl BitbucketCloud {
+    /// `project` is the workspace. The API is authenticated with an app password.
+    pub fn new(project: &str, repository: &str, id: u32, endpoints: Endpoints) -> BitbucketCloud {
+        let username = std::env::var("GIT_USERNAME").expect("GIT_USERNAME is not set");
+        let password = std::env::var("GIT_PASSWORD").expect("GIT_PASSWORD is not set");
+        let auth = format!("{}:{}", username, password);
+        let mut header = reqwest::header::HeaderMap::new();
+        let basic_auth = format!("Basic {}", base64::encode(&auth));
+        header.insert(
+            reqwest::header::AUTHORIZATION,
+            reqwest::header::HeaderValue::from_str(&basic_auth).unwrap(),
+        );
+        header.insert(
+            reqwest::header::ACCEPT,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        header.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        let client = reqwest::blocking::ClientBuilder::new()
+            .default_headers(header)
+            .build()
+            .unwrap();
+        let base_url = format!(
+            "{}/repositories/{}/{}/pullrequests/{}",
+            endpoints.api, project, repository, id
+        );
+        BitbucketCloud {
+            client: client.to_owned(),
+            endpoints,
+            base_url,
+        }
+    }
+
+    fn get_pull_request(&self) -> anyhow::Result<CloudPullRequestPayload> {
+        let resp = self.client.get(&self.base_url).send()?;
+        if resp.status().is_success() {
+            Ok(resp.json()?)
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    /// Every value of a paged resource, the pages are linked by their `next` URL.
+    fn get_pages<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<Vec<T>> {
+        let mut values: Vec<T> = vec![];
+        let mut url = format!("{}?pagelen={}", url, PAGE_LENGTH);
+        loop {
+            let resp = self.client.get(&url).send()?;
+            if !resp.status().is_success() {
+                return Err(ApiError(resp.status().to_string()).into());
+            }
+            let payload: CloudPagePayload = resp.json()?;
+            let page: Vec<T> = serde_json::from_value(payload.values)?;
+            values.extend(page);
+            match payload.next {
+                Some(next) => url = next,
+                None => return Ok(values),
+            }
+        }
+    }
+
+    fn get_changed_files(&self) -> anyhow::Result<Vec<Change>> {
+        let url = format!("{}/diffstat", self.base_url);
+        let mut files: Vec<Change> = vec![];
+        for value in self.get_pages::<CloudDiffstatPayload>(&url)? {
+            let old = value.old.map(|x| x.path);
+            let change = match (value.status.as_str(), value.new) {
+                ("added", Some(new)) => Change::new(&new.path, ChangeKind::Added),
+                ("renamed", Some(new)) => Change {
+                    from: old,
+                    ..Change::new(&new.path, ChangeKind::Renamed)
+                },
+                (_, Some(new)) => Change::new(&new.path, ChangeKind::Modified),
+                (_, None) => match old {
+                    Some(path) => Change::new(&path, ChangeKind::Deleted),
+                    None => continue,
+                },
+            };
+            files.push(change);
+        }
+        Ok(files)
+    }
+
+    /// Comments of the pull-request which aren't deleted.
+    fn get_comments(&self) -> anyhow::Result<Vec<CloudComment>> {
+        let url = format!("{}/comments", self.base_url);
+        let comments = self.get_pages::<CloudComment>(&url)?;
+        Ok(comments.into_iter().filter(|x| !x.deleted).collect())
+    }
+
+    fn post_comment(&self, body: &serde_json::Value) -> anyhow::Result<()> {
+        let url = format!("{}/comments", self.base_url);
+        let resp = self.client.post(&url).json(body).send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    fn update_comment(&self, id: i64, message: &str) -> anyhow::Result<()> {
+        let url = format!("{}/comments/{}", self.base_url, id);
+        let body = json!({ "content": { "raw": message } });
+        let resp = self.client.put(&url).json(&body).send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    fn resolve_comment(&self, id: i64) -> anyhow::Result<()> {
+        let url = format!("{}/comments/{}/resolve", self.base_url, id);
+        let resp = self.client.post(&url).send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    /// Comment on every offending file like on Bitbucket Server. Bitbucket Cloud doesn't
+    /// apply suggestion blocks, so the header is always quoted as a code block.
+    fn review(&self, files: &[Change], report: &Report) -> anyhow::Result<()> {
+        let mut comments: Vec<FileComment<i64>> = vec![];
+        for comment in self.get_comments()? {
+            if let Some(path) = file_comment_path(&comment.content.raw) {
+                comments.push(FileComment {
+                    id: comment.id,
+                    path: path.to_string(),
+                    open: comment.resolution.is_none(),
+                    outdated: comment.inline.as_ref().is_some_and(|x| x.outdated),
+                });
+            }
+        }
+        let (stale, new) = reconcile_file_comments(comments, files, report);
+        for comment in stale {
+            tracing::info!("resolve comment on {}", comment.path);
+            self.resolve_comment(comment.id)?;
+        }
+        for comment in new {
+            let finding = comment.finding;
+            let inline = match comment.line {
+                Some(line) => json!({ "path": finding.path, "to": line }),
+                None => json!({ "path": finding.path }),
+            };
+            let body = json!({
+                "content": { "raw": render_file_comment(finding, false) },
+                "inline": inline,
+            });
+            tracing::info!("comment on {}", finding.path);
+            self.post_comment(&body)?;
+        }
+        Ok(())
+    }
+
+    /// Set the build status of the scan on the commit of the repository `full_name`,
+    /// `url` is where the status links to.
+    fn set_status(
+        &self,
+        full_name: &str,
+        commit: &str,
+        url: &str,
+        state: BuildState,
+        description: &str,
+    ) -> anyhow::Result<()> {
+        let state = match state {
+            BuildState::InProgress => "INPROGRESS",
+            BuildState::Successful => "SUCCESSFUL",
+            BuildState::Failed => "FAILED",
+            BuildState::Cancelled => "STOPPED",
+        };
+        let body = json!({
+            "state": state,
+            "key": CHECK_NAME,
+            "name": "Copyright",
+            "url": url,
+            "description": description,
+        });
+        let resp = self
+            .client
+            .post(format!(
+                "{}/repositories/{}/commit/{}/statuses/build",
+                self.endpoints.api, full_name, commit
+            ))
+            .json(&body)
+            .send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+}
+
+impl Handler for BitbucketCloud {
+    fn execute(
+        &mut self,
+        _project: &str,
+        _repository: &str,
+        id: u32,
+        options: &Options,
+    ) -> anyhow::Result<Report> {
+        let pull_request = self.get_pull_request()?;
+        let files = self.get_changed_files()?;
+        let source = &pull_request.source;
+        let commit = options.commit.as_deref().unwrap_or(&source.commit.hash);
+        // the status shows up on the pull-request when it's set in the source repository
+        let full_name = source.repository.full_name.as_str();
+        let link = match &pull_request.links.html {
+            Some(x) => x.href.to_string(),
+            None => self.base_url.to_string(),
+        };
+        self.set_status(
+            full_name,
+            commit,
+            &link,
+            BuildState::InProgress,
+            "copyright check is running",
+        )?;
+        // there are no pull-request refs, the source branch is fetched from its repository
+        let result = git_fetch(
+            &files,
+            &self.endpoints.clone_url(full_name),
+            &format!("refs/heads/{}", source.branch.name),
+            id,
+        )
+        .and_then(|_| {
+            let same_repository = full_name == pull_request.destination.repository.full_name;
+            let branch = match options.fix {
+                true if !same_repository => {
+                    tracing::warn!("cannot push fixes to a pull-request from fork");
+                    None
+                }
+                true => Some(source.branch.name.as_str()),
+                false => None,
+            };
+            scan(&files, branch)
+        });
+        let (state, description) = conclude(&result);
+        self.set_status(full_name, commit, &link, state, &description)?;
+        let report = result?;
+        self.review(&files, &report)?;
+        let comment = self
+            .get_comments()?
+            .into_iter()
+            .find(|x| x.inline.is_none() && is_report_comment(&x.content.raw));
+        let previous = comment.as_ref().map(|x| x.content.raw.as_str());
+        let message = render_report_comment(&report, commit, previous);
+        match &comment {
+            Some(comment) => {
+                tracing::info!("update comment in pull-request");
+                self.update_comment(comment.id, &message)?;
+            }
+            None if !report.findings.is_empty() => {
+                tracing::info!("report comment to pull-request");
+                self.post_comment(&json!({ "content": { "raw": message } }))?;
+            }
+            None => tracing::info!("nothing to report to pull-request"),
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::fake;
+    use axum::extract::Query;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    const DIFFSTAT: &str = "/2.0/repositories/workspace/repo/pullrequests/1/diffstat";
+
+    /// Diffstat of the fake pull-request, the first page links the second one.
+    async fn diffstat(
+        headers: HeaderMap,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let basic = headers.get("Authorization").and_then(|x| x.to_str().ok());
+        if basic != Some(&format!("Basic {}", base64::encode("fake:secret"))) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        let page = match query.get("page").map(|x| x.as_str()) {
+            None => {
+                assert_eq!(query.get("pagelen"), Some(&PAGE_LENGTH.to_string()));
+                let host = headers.get("Host").unwrap().to_str().unwrap();
+                json!({
+                    "values": [
+                        {"status": "added", "old": null, "new": {"path": "src/a.rs"}},
+                        {"status": "modified", "old": {"path": "src/b.rs"}, "new": {"path": "src/b.rs"}},
+                    ],
+                    "next": format!("http://{}{}?pagelen={}&page=2", host, DIFFSTAT, PAGE_LENGTH),
+                })
+            }
+            Some(_) => json!({
+                "values": [
+                    {"status": "renamed", "old": {"path": "src/c.rs"}, "new": {"path": "src/d.rs"}},
+                    {"status": "removed", "old": {"path": "src/e.rs"}, "new": null},
+                ],
+            }),
+        };
+        Ok(Json(page))
+    }
+
+    #[test]
+    fn diffstat_pages_are_followed() {
+        fake::credentials();
+        let url = fake::serve(Router::new().route(DIFFSTAT, get(diffstat)));
+        let endpoints = Endpoints::new(
+            &format!("{}/2.0/", url),
+            "https://bitbucket.org/{project}/{repository}.git",
+        );
+        let cloud = BitbucketCloud::new("workspace", "repo", 1, endpoints);
+        let files = cloud.get_changed_files().unwrap();
+        assert_eq!(
+            files,
+            vec![
+                Change::new("src/a.rs", ChangeKind::Added),
+                Change::new("src/b.rs", ChangeKind::Modified),
+                Change {
+                    from: Some("src/c.rs".to_string()),
+                    ..Change::new("src/d.rs", ChangeKind::Renamed)
+                },
+                Change::new("src/e.rs", ChangeKind::Deleted),
+            ]
+        );
+        assert_eq!(
+            cloud.endpoints.clone_url("workspace/repo"),
+            "https://bitbucket.org/workspace/repo.git"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const CONFIG_FILES: &[&str] = &["pipeline.yaml", "pipeline.yml"];
+
+/// `copyright:` in pipeline.yaml is either a plain list of excluded globs or the whole policy.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+struct Filter {
+    #[serde(default)]
+    copyright: serde_yaml::Value,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// globs of files which aren't checked
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// accepted headers, the built-in header of the language is used
+    /// for files which no template is assigned to
+    #[serde(default)]
+    pub templates: Vec<TemplateConfig>,
+    #[serde(default)]
+    pub year: YearPolicy,
+}
+
+/// How the year in a copyright header is validated.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum YearPolicy {
+    /// years are ignored
+    #[default]
+    None,
+    /// the header has to mention a year
+    Present,
+    /// the header of a modified file has to mention the current year
+    CurrentYearForModifiedFiles,
+    /// the header of a modified file has to end its year range with the year of the commit
+    RangeEndIsCommitYear,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    /// globs of files the template is assigned to, every file when it's empty
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// header text without comment decoration
+    pub text: Option<String>,
+    /// repo-relative file which holds the header text
+    pub file: Option<String>,
+    /// value of the `{{holder}}` placeholder
+    pub holder: Option<String>,
+}
+
+impl TemplateConfig {
+    /// Header text of the template, loaded from the workspace when it's given by `file`.
+    pub fn load_text(&self, workspace: &Path) -> anyhow::Result<String> {
+        match (&self.text, &self.file) {
+            (Some(text), None) => Ok(text.to_string()),
+            (None, Some(file)) => std::fs::read_to_string(workspace.join(file))
+                .map_err(|e| anyhow::anyhow!("template file {}: {}", file, e)),
+            _ => Err(anyhow::anyhow!(
+                "template needs exactly one of 'text' and 'file'"
+            )),
+        }
+    }
+}
+
+/// Whether the repo-relative path is the pipeline configuration itself.
+pub fn is_config_file(related_path: &str) -> bool {
+    CONFIG_FILES.contains(&related_path)
+}
+
+/// Load the copyright policy from pipeline.yaml (or pipeline.yml) in the workspace,
+/// the default policy is returned when there is no such file or section.
+pub fn load(workspace: &Path) -> anyhow::Result<Policy> {
+    let conf = match CONFIG_FILES
+        .iter()
+        .map(|x| workspace.join(x))
+        .find(|x| x.is_file())
+    {
+        Some(x) => x,
+        None => return Ok(Policy::default()),
+    };
+    let content = std::fs::read_to_string(&conf)?;
+    let error = |e: serde_yaml::Error| anyhow::anyhow!("{}: {}", conf.display(), e);
+    let filter: Filter = serde_yaml::from_str(&content).map_err(error)?;
+    match filter.copyright {
+        serde_yaml::Value::Null => Ok(Policy::default()),
+        serde_yaml::Value::Sequence(_) => Ok(Policy {
+            exclude: serde_yaml::from_value(filter.copyright).map_err(error)?,
+            ..Default::default()
+        }),
+        x => serde_yaml::from_value(x).map_err(error),
+    }
+}
@@ -1,145 +1,750 @@
-use crate::action::{COPYRIGHT_CSHARP, COPYRIGHT_JS};
+use crate::action::config::{self, YearPolicy};
+use crate::action::fixer::{self, Suggestion};
+use crate::action::language::{self, Language, LANGUAGES};
+use crate::action::template::{self, CommentStyle, Template};
+use chrono::Datelike;
 use rayon::prelude::*;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::Read;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct Filter {
-    copyright: Vec<String>,
+// how many leading lines are inspected for a copyright notice that doesn't match the template
+const HEADER_SEARCH_LINES: usize = 30;
+
+const MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    Missing,
+    Malformed,
+    MissingYear,
+    OutdatedYear,
+    DecodeError,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Reason::Missing => "copyright header is missing",
+            Reason::Malformed => "copyright header doesn't match the template",
+            Reason::MissingYear => "copyright header has no year",
+            Reason::OutdatedYear => "copyright year is outdated",
+            Reason::DecodeError => "file content cannot be decoded",
+        };
+        f.write_str(text)
+    }
+}
+
+/// A file that doesn't satisfy the copyright policy.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Finding {
+    /// path relative to the workspace, always separated by '/'
+    pub path: String,
+    pub language: String,
+    pub reason: Reason,
+    /// 1-based, inclusive line span the finding refers to
+    pub lines: (usize, usize),
+    /// how the header is inserted, see `Detector::suggest`
+    #[serde(skip)]
+    pub suggestion: Option<Suggestion>,
+}
+
+/// A file which is checked by the detector.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Scanned {
+    pub path: String,
+    pub language: String,
+}
+
+/// How a pull-request changes a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+/// A file changed by a pull-request, `path` is relative to the workspace.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+    /// path before the file was renamed, if the SCM tells it
+    pub from: Option<String>,
+}
+
+impl Change {
+    pub fn new(path: &str, kind: ChangeKind) -> Change {
+        Change {
+            path: path.to_string(),
+            kind,
+            from: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    Deleted,
+    Renamed,
+    Unsupported,
+    Excluded,
+    NotFound,
+    Configuration,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            SkipReason::Deleted => "file is deleted",
+            SkipReason::Renamed => "file is renamed",
+            SkipReason::Unsupported => "language is not supported",
+            SkipReason::Excluded => "file is excluded",
+            SkipReason::NotFound => "file is not found in the workspace",
+            SkipReason::Configuration => "file is the pipeline configuration",
+        };
+        f.write_str(text)
+    }
+}
+
+/// A changed file which isn't checked.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Skipped {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+/// Outcome of a scan, findings are sorted by path.
+#[derive(Serialize, Clone, PartialEq, Debug, Default)]
+pub struct Report {
+    pub scanned: Vec<Scanned>,
+    pub findings: Vec<Finding>,
+    /// files which the header has been inserted into
+    pub fixed: Vec<String>,
+    pub skipped: Vec<Skipped>,
+}
+
+impl Report {
+    /// Record the files fixed by `Detector::fix`, their findings are dropped.
+    pub fn mark_fixed(&mut self, fixed: Vec<String>) {
+        self.findings.retain(|x| !fixed.contains(&x.path));
+        self.fixed = fixed;
+    }
+}
+
+/// A header template from pipeline.yaml and the files it's assigned to.
+struct Rule {
+    files: Vec<glob::Pattern>,
+    template: Template,
+    patterns: HashMap<&'static str, Regex>,
 }
 
 pub struct Detector {
     copyright_matches: HashMap<&'static str, Regex>,
     filters: Vec<glob::Pattern>,
+    rules: Vec<Rule>,
+    year_policy: YearPolicy,
+    /// the year a modified file's header has to end with
+    reference_year: i32,
+    /// files changed by the pull-request or since `--base`; when it's None no file counts
+    /// as modified, so a scan of the whole tree doesn't report every old header
+    modified: Option<HashSet<String>>,
     workspace: PathBuf,
 }
 
 impl Detector {
-    pub fn new() -> Detector {
-        let mut filters: Vec<glob::Pattern> = vec![];
-        let workspace = std::env::current_dir().expect("cannot get work dir");
-        let mut conf = workspace.join("pipeline.yaml");
-        if !conf.is_file() {
-            conf = workspace.join("pipeline.yml");
-        }
-        if conf.is_file() {
-            let data: Result<Filter, anyhow::Error> = std::fs::read_to_string(conf)
-                .map_err(|e| anyhow::anyhow!(e.to_string()))
-                .and_then(|x| serde_yaml::from_str(&x).map_err(|e| anyhow::anyhow!(e.to_string())));
-            if let Ok(o) = data {
-                for f in o.copyright.iter() {
-                    let pattern = glob::Pattern::new(f);
-                    if let Ok(p) = pattern {
-                        filters.push(p);
-                    }
-                }
-            }
+    pub fn new(workspace: &Path) -> anyhow::Result<Detector> {
+        let workspace = workspace
+            .canonicalize()
+            .unwrap_or_else(|_| workspace.to_path_buf());
+        let policy = config::load(&workspace)?;
+        let filters = compile_globs(&policy.exclude)?;
+        let mut rules: Vec<Rule> = vec![];
+        for (idx, conf) in policy.templates.iter().enumerate() {
+            let template = conf
+                .load_text(&workspace)
+                .and_then(|x| Template::parse(&x, conf.holder.as_deref()))
+                .map_err(|e| anyhow::anyhow!("copyright template #{}: {}", idx + 1, e))?;
+            let patterns = LANGUAGES
+                .iter()
+                .map(|x| (x.name, template.pattern(&x.comment)))
+                .collect();
+            rules.push(Rule {
+                files: compile_globs(&conf.files)?,
+                template,
+                patterns,
+            });
         }
-        let copyright_matches = HashMap::from([
-            ("cs", create_copyright_reg(COPYRIGHT_CSHARP)),
-            ("js", create_copyright_reg(COPYRIGHT_JS)),
-        ]);
-        Detector {
+        let copyright_matches = LANGUAGES
+            .iter()
+            .map(|x| (x.name, Template::new(x.template).pattern(&x.comment)))
+            .collect();
+        let reference_year = match policy.year {
+            YearPolicy::RangeEndIsCommitYear => commit_year(&workspace),
+            _ => chrono::Local::now().year(),
+        };
+        Ok(Detector {
             workspace,
             filters,
+            rules,
+            year_policy: policy.year,
+            reference_year,
+            modified: None,
             copyright_matches,
+        })
+    }
+
+    pub fn scan(&self) -> anyhow::Result<Report> {
+        self.scan_paths(std::slice::from_ref(&self.workspace))
+    }
+
+    /// Scan the given files and directories, directories are walked recursively.
+    /// Nothing is modified, the year policies of modified files don't apply.
+    pub fn scan_paths(&self, paths: &[PathBuf]) -> anyhow::Result<Report> {
+        let mut files: Vec<PathBuf> = vec![];
+        for path in paths.iter() {
+            let path = path
+                .canonicalize()
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            for entry in walkdir::WalkDir::new(path)
+                .into_iter()
+                .filter_entry(|e| !self.is_hidden(e))
+                .filter_map(|e| e.ok())
+            {
+                if entry.metadata().unwrap().is_file() {
+                    files.push(entry.path().to_path_buf());
+                }
+            }
         }
+        self.check(files, vec![])
     }
 
-    pub fn scan(&self) -> anyhow::Result<bool> {
+    /// Scan exactly the files changed by a pull-request, the year policy is limited to them.
+    /// Deleted, unsupported and excluded files are skipped, so is the side a file is renamed from.
+    pub fn scan_changes(&mut self, changes: &[Change]) -> anyhow::Result<Report> {
+        self.modified = Some(
+            changes
+                .iter()
+                .filter(|x| x.kind != ChangeKind::Deleted)
+                .map(|x| x.path.to_string())
+                .collect(),
+        );
         let mut files: Vec<PathBuf> = vec![];
-        for entry in walkdir::WalkDir::new(&self.workspace)
-            .into_iter()
-            .filter_entry(|e| !self.is_hidden(e))
-            .filter_map(|e| e.ok())
-        {
-            if entry.metadata().unwrap().is_file() {
-                files.push(entry.path().to_path_buf());
+        let mut skipped: Vec<Skipped> = vec![];
+        for change in changes.iter() {
+            if let Some(from) = &change.from {
+                skipped.push(Skipped {
+                    path: from.to_string(),
+                    reason: SkipReason::Renamed,
+                });
+            }
+            match self.skip_reason(change) {
+                Some(reason) => skipped.push(Skipped {
+                    path: change.path.to_string(),
+                    reason,
+                }),
+                None => files.push(self.workspace.join(&change.path)),
+            }
+        }
+        self.check(files, skipped)
+    }
+
+    fn skip_reason(&self, change: &Change) -> Option<SkipReason> {
+        let path = self.workspace.join(&change.path);
+        if change.kind == ChangeKind::Deleted {
+            Some(SkipReason::Deleted)
+        } else if config::is_config_file(&change.path) {
+            Some(SkipReason::Configuration)
+        } else if language::find(&path).is_none() {
+            Some(SkipReason::Unsupported)
+        } else if self.is_excluded(&change.path) {
+            Some(SkipReason::Excluded)
+        } else if !path.is_file() {
+            Some(SkipReason::NotFound)
+        } else {
+            None
+        }
+    }
+
+    /// A file which cannot be read fails the scan, it would pass unchecked otherwise.
+    fn check(&self, mut files: Vec<PathBuf>, mut skipped: Vec<Skipped>) -> anyhow::Result<Report> {
+        files.sort();
+        files.dedup();
+        files.retain(|x| {
+            let related_path = self.related_path(x);
+            if config::is_config_file(&related_path) {
+                skipped.push(Skipped {
+                    path: related_path,
+                    reason: SkipReason::Configuration,
+                });
+                return false;
+            }
+            true
+        });
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped.dedup();
+        let scanned = files
+            .iter()
+            .filter_map(|x| {
+                language::find(x).map(|l| Scanned {
+                    path: self.related_path(x),
+                    language: l.name.to_string(),
+                })
+            })
+            .collect();
+        let mut findings: Vec<Finding> = files
+            .par_iter()
+            .filter_map(|x| self.parse(x).transpose())
+            .collect::<anyhow::Result<_>>()?;
+        findings.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Report {
+            scanned,
+            findings,
+            fixed: vec![],
+            skipped,
+        })
+    }
+
+    /// Insert the header template into files whose header is missing,
+    /// return the paths which are fixed.
+    pub fn fix(&self, findings: &[Finding]) -> Vec<String> {
+        findings
+            .iter()
+            .filter(|x| x.reason == Reason::Missing)
+            .filter_map(|x| {
+                let path = self.workspace.join(&x.path);
+                let language = language::find(&path)?;
+                let header = self.header(&x.path, language);
+                match header.and_then(|h| fixer::insert_header(&path, &h)) {
+                    Ok(_) => Some(x.path.clone()),
+                    Err(err) => {
+                        tracing::error!("fail to fix {}: {}", x.path, err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Attach the suggestion how to insert the header to the findings of missing headers.
+    pub fn suggest(&self, findings: &mut [Finding]) {
+        for finding in findings.iter_mut() {
+            if finding.reason != Reason::Missing {
+                continue;
             }
+            let path = self.workspace.join(&finding.path);
+            let language = match language::find(&path) {
+                Some(x) => x,
+                None => continue,
+            };
+            let header = self.header(&finding.path, language);
+            match header.and_then(|h| fixer::suggest_header(&path, &h)) {
+                Ok(suggestion) => finding.suggestion = suggestion,
+                Err(err) => tracing::error!("fail to suggest {}: {}", finding.path, err),
+            }
+        }
+    }
+
+    /// The header which is inserted into a file, from its template or the built-in one.
+    fn header(&self, related_path: &str, language: &Language) -> anyhow::Result<String> {
+        let year = match self.year_policy {
+            YearPolicy::None => None,
+            _ => Some(self.reference_year),
+        };
+        match self.rules.iter().find(|r| r.applies_to(related_path)) {
+            Some(rule) => rule.template.render(&language.comment, year),
+            None => Template::new(language.template).render(&language.comment, year),
         }
-        let number: i32 = files.par_iter().map(|x| self.parse(x)).sum();
-        Ok(number > 0)
     }
 
     fn is_hidden(&self, entry: &walkdir::DirEntry) -> bool {
-        let yes = entry
-            .file_name()
-            .to_str()
-            .map(|s| s.starts_with("."))
-            .unwrap_or(false);
+        // a path given explicitly is scanned even if its name starts with '.'
+        let yes = entry.depth() > 0
+            && entry
+                .file_name()
+                .to_str()
+                .map(|s| s.starts_with('.'))
+                .unwrap_or(false);
         if yes {
             return true;
         }
         let related_path = match entry.path().strip_prefix(&self.workspace) {
-            Ok(p) => p.to_str().unwrap().replace("\\", "/"),
+            Ok(p) => p.to_str().unwrap().replace('\\', "/"),
             Err(_x) => "".to_string(),
         };
-        let pos = self.filters.iter().position(|x| {
-            x.matches_with(
-                &related_path,
-                glob::MatchOptions {
-                    case_sensitive: false,
-                    require_literal_separator: true,
-                    require_literal_leading_dot: true,
-                },
-            )
-        });
-        match pos {
-            Some(_x) => return true,
-            None => false,
-        }
-    }
-    fn parse(&self, path: &PathBuf) -> i32 {
-        let ext = path.extension().unwrap().to_str().unwrap();
-        if ["cs", "js"].contains(&ext) {
-            let ret = parse_file(&path, self.copyright_matches.get(ext).unwrap());
-            match ret {
-                Ok(found) => {
-                    if found {
-                        return 1;
-                    }
-                }
-                Err(err) => tracing::error!("{}", err),
+        self.filters
+            .iter()
+            .any(|x| x.matches_with(&related_path, MATCH_OPTIONS))
+    }
+
+    /// Whether a repo-relative path is hidden or excluded by pipeline.yaml, the same
+    /// as the walk in `scan_paths` does, an excluded directory excludes its content.
+    fn is_excluded(&self, related_path: &str) -> bool {
+        let mut prefix = String::new();
+        for name in related_path.split('/') {
+            if name.starts_with('.') {
+                return true;
+            }
+            if !prefix.is_empty() {
+                prefix.push('/');
             }
+            prefix.push_str(name);
+            if self
+                .filters
+                .iter()
+                .any(|x| x.matches_with(&prefix, MATCH_OPTIONS))
+            {
+                return true;
+            }
+        }
+        false
+    }
+    fn parse(&self, path: &Path) -> anyhow::Result<Option<Finding>> {
+        let language = match language::find(path) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let related_path = self.related_path(path);
+        let finding = |reason: Reason, lines: (usize, usize)| Finding {
+            path: related_path.clone(),
+            language: language.name.to_string(),
+            reason,
+            lines,
+            suggestion: None,
+        };
+        let verdict = parse_file(
+            path,
+            &self.patterns(&related_path, language),
+            &language.comment,
+            self.reference_year,
+        )
+        .map_err(|e| anyhow::anyhow!("{}: {}", related_path, e))?;
+        Ok(match verdict {
+            Verdict::Compliant(header) => self
+                .check_year(&related_path, header.years)
+                .map(|x| finding(x, header.lines)),
+            Verdict::Missing => Some(finding(Reason::Missing, (1, 1))),
+            Verdict::Malformed(lines) => Some(finding(Reason::Malformed, lines)),
+            Verdict::Undecodable => Some(finding(Reason::DecodeError, (1, 1))),
+        })
+    }
+
+    fn check_year(&self, related_path: &str, years: Option<(i32, i32)>) -> Option<Reason> {
+        let modified = self
+            .modified
+            .as_ref()
+            .is_some_and(|x| x.contains(related_path));
+        match (self.year_policy, years) {
+            (YearPolicy::None, _) => None,
+            (YearPolicy::Present, None) => Some(Reason::MissingYear),
+            (YearPolicy::Present, Some(_)) => None,
+            (_, _) if !modified => None,
+            (_, None) => Some(Reason::MissingYear),
+            (_, Some((_, end))) if end != self.reference_year => Some(Reason::OutdatedYear),
+            (_, Some(_)) => None,
+        }
+    }
+
+    fn related_path(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.workspace) {
+            Ok(p) => p.to_str().unwrap().replace('\\', "/"),
+            Err(_x) => path.to_str().unwrap().replace('\\', "/"),
         }
-        0
     }
+
+    /// Accepted headers of a file: the templates assigned to it by pipeline.yaml,
+    /// or the built-in header of its language.
+    fn patterns(&self, related_path: &str, language: &Language) -> Vec<&Regex> {
+        let patterns: Vec<&Regex> = self
+            .rules
+            .iter()
+            .filter(|x| x.applies_to(related_path))
+            .filter_map(|x| x.patterns.get(language.name))
+            .collect();
+        if patterns.is_empty() {
+            return self
+                .copyright_matches
+                .get(language.name)
+                .into_iter()
+                .collect();
+        }
+        patterns
+    }
+}
+
+impl Rule {
+    fn applies_to(&self, related_path: &str) -> bool {
+        self.files.is_empty()
+            || self
+                .files
+                .iter()
+                .any(|x| x.matches_with(related_path, MATCH_OPTIONS))
+    }
+}
+
+fn compile_globs(globs: &[String]) -> anyhow::Result<Vec<glob::Pattern>> {
+    globs
+        .iter()
+        .map(|x| glob::Pattern::new(x).map_err(|e| anyhow::anyhow!("glob '{}': {}", x, e)))
+        .collect()
 }
 
-fn parse_file(file: &PathBuf, pattern: &Regex) -> anyhow::Result<bool> {
-    let mut f = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(false)
-        .open(file)?;
-    // read file
-    let mut bytes = Vec::new();
-    f.read_to_end(&mut bytes)?;
+/// The header found in a file.
+struct Header {
+    years: Option<(i32, i32)>,
+    lines: (usize, usize),
+}
+
+enum Verdict {
+    Compliant(Header),
+    Missing,
+    Malformed((usize, usize)),
+    Undecodable,
+}
+
+/// `present` is the year a range such as `2015-present` ends with.
+fn parse_file(
+    file: &Path,
+    patterns: &[&Regex],
+    comment: &CommentStyle,
+    present: i32,
+) -> anyhow::Result<Verdict> {
+    // only the fixer writes, a read-only file is checked as well
+    let bytes = std::fs::read(file)?;
     let encoding = encoding_rs::Encoding::for_bom(&bytes);
     let text = match encoding {
         Some(x) => x.0.decode_with_bom_removal(&bytes),
         None => encoding_rs::UTF_8.decode_with_bom_removal(&bytes),
     };
     if text.1 {
-        return Err(anyhow::anyhow!("fail to decode file content"));
+        return Ok(Verdict::Undecodable);
     }
-    let found = pattern.is_match(&text.0);
-    if found {
-        return Ok(false);
+    if let Some(found) = patterns.iter().find_map(|x| x.find(&text.0)) {
+        let line_of = |offset: usize| text.0[..offset].matches('\n').count() + 1;
+        let header = found.as_str().trim_end();
+        return Ok(Verdict::Compliant(Header {
+            years: template::find_years(header, present),
+            lines: (
+                line_of(found.start()),
+                line_of(found.start() + header.len()),
+            ),
+        }));
+    }
+    match find_copyright_block(&text.0, comment) {
+        Some(lines) => Ok(Verdict::Malformed(lines)),
+        None => Ok(Verdict::Missing),
     }
-    Ok(true)
 }
 
-fn create_copyright_reg(content: &str) -> Regex {
-    let reg_str = content
-        .lines()
-        .into_iter()
-        .map(|line| format!("{}\\s*\\n", line))
-        .collect::<Vec<String>>()
-        .join("\n");
-    Regex::new(&reg_str).unwrap()
+/// Locate the comment block which mentions copyright near the top of the file,
+/// it's used to point at a header which exists but doesn't match the template.
+fn find_copyright_block(text: &str, comment: &CommentStyle) -> Option<(usize, usize)> {
+    let lines: Vec<&str> = text.lines().take(HEADER_SEARCH_LINES).collect();
+    let hit = lines
+        .iter()
+        .position(|x| x.to_lowercase().contains("copyright"))?;
+    let is_comment = |line: &str| comment.is_comment(line);
+    let mut start = hit;
+    while start > 0 && is_comment(lines[start - 1]) {
+        start -= 1;
+    }
+    let mut end = hit;
+    while end + 1 < lines.len() && is_comment(lines[end + 1]) && !comment.is_closed(lines[end]) {
+        end += 1;
+    }
+    Some((start + 1, end + 1))
+}
+
+/// Year of the checked out commit, the current year when it cannot be told.
+fn commit_year(workspace: &Path) -> i32 {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(workspace)
+        .args(["log", "-1", "--format=%cd", "--date=format:%Y"])
+        .output();
+    match output {
+        Ok(x) if x.status.success() => String::from_utf8_lossy(&x.stdout)
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| chrono::Local::now().year()),
+        _ => {
+            tracing::warn!("cannot get the commit year, use the current year");
+            chrono::Local::now().year()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIPELINE: &str = r#"copyright:
+  templates:
+    - text: "Copyright {{year}} Example Corp."
+"#;
+
+    /// A workspace with pipeline.yaml and the given files.
+    fn workspace(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("pipeline.yaml"), PIPELINE).unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn policy(year: &str) -> String {
+        format!("{}  year: {}\n", PIPELINE, year)
+    }
+
+    /// A detector of the workspace whose current and commit year is 2024.
+    fn detector(dir: &tempfile::TempDir) -> Detector {
+        let mut detector = Detector::new(dir.path()).unwrap();
+        detector.reference_year = 2024;
+        detector
+    }
+
+    fn reasons(report: &Report) -> Vec<(&str, Reason)> {
+        report
+            .findings
+            .iter()
+            .map(|x| (x.path.as_str(), x.reason))
+            .collect()
+    }
+
+    #[test]
+    fn read_only_file_is_checked() {
+        let dir = workspace(&[
+            ("a.cs", "class A {}\n"),
+            ("b.cs", "// Copyright 2020 Example Corp.\nclass B {}\n"),
+        ]);
+        for name in ["a.cs", "b.cs"] {
+            let path = dir.path().join(name);
+            let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+            permissions.set_readonly(true);
+            std::fs::set_permissions(&path, permissions).unwrap();
+        }
+        let report = Detector::new(dir.path()).unwrap().scan().unwrap();
+        assert_eq!(reasons(&report), vec![("a.cs", Reason::Missing)]);
+        assert_eq!(report.scanned.len(), 2);
+    }
+
+    #[test]
+    fn unreadable_file_fails_the_scan() {
+        let dir = workspace(&[("a.cs", "class A {}\n")]);
+        let detector = Detector::new(dir.path()).unwrap();
+        let files = vec![
+            detector.workspace.join("a.cs"),
+            detector.workspace.join("gone.cs"),
+        ];
+        let err = detector.check(files, vec![]).unwrap_err();
+        assert!(err.to_string().starts_with("gone.cs: "), "{}", err);
+    }
+
+    #[test]
+    fn years_are_checked_by_policy() {
+        let dir = workspace(&[]);
+        let mut detector = detector(&dir);
+        detector.modified = Some(HashSet::from(["a.cs".to_string()]));
+        let cases = [
+            (YearPolicy::None, None, None),
+            (YearPolicy::None, Some((2009, 2009)), None),
+            (YearPolicy::Present, None, Some(Reason::MissingYear)),
+            (YearPolicy::Present, Some((2009, 2009)), None),
+            (YearPolicy::Present, Some((2030, 2030)), None),
+        ];
+        for (policy, years, expected) in cases {
+            detector.year_policy = policy;
+            assert_eq!(detector.check_year("a.cs", years), expected);
+            assert_eq!(detector.check_year("b.cs", years), expected);
+        }
+        for policy in [
+            YearPolicy::CurrentYearForModifiedFiles,
+            YearPolicy::RangeEndIsCommitYear,
+        ] {
+            detector.year_policy = policy;
+            let cases = [
+                (None, Some(Reason::MissingYear)),
+                (Some((2024, 2024)), None),
+                (Some((2015, 2024)), None),
+                (Some((2015, 2022)), Some(Reason::OutdatedYear)),
+                // a year in the future is not the expected one either
+                (Some((2030, 2030)), Some(Reason::OutdatedYear)),
+            ];
+            for (years, expected) in cases {
+                assert_eq!(detector.check_year("a.cs", years), expected, "{:?}", years);
+                // an unmodified file keeps its header
+                assert_eq!(detector.check_year("b.cs", years), None);
+            }
+        }
+    }
+
+    #[test]
+    fn years_are_parsed_from_headers() {
+        let dir = workspace(&[
+            ("pipeline.yaml", &policy("current-year-for-modified-files")),
+            ("single.cs", "// Copyright 2024 Example Corp.\n"),
+            ("range.cs", "// Copyright 2015-2024 Example Corp.\n"),
+            ("present.cs", "// Copyright 2019-present Example Corp.\n"),
+            ("dash.cs", "// Copyright 2015 – 2022 Example Corp.\n"),
+            ("future.cs", "// Copyright 2030 Example Corp.\n"),
+            ("none.cs", "// Copyright Example Corp.\n"),
+        ]);
+        let names = [
+            "single.cs",
+            "range.cs",
+            "present.cs",
+            "dash.cs",
+            "future.cs",
+            "none.cs",
+        ];
+        let changes: Vec<Change> = names
+            .iter()
+            .map(|x| Change::new(x, ChangeKind::Modified))
+            .collect();
+        let report = detector(&dir).scan_changes(&changes).unwrap();
+        assert_eq!(
+            reasons(&report),
+            vec![
+                ("dash.cs", Reason::OutdatedYear),
+                ("future.cs", Reason::OutdatedYear),
+                // the template has a year, a header without one doesn't match it
+                ("none.cs", Reason::Malformed),
+            ]
+        );
+    }
+
+    #[test]
+    fn full_scan_reports_no_outdated_year() {
+        let files = [
+            ("old.js", "// Copyright 2019-2022 Example Corp.\n"),
+            ("oss/a.go", "// Copyright 2020 Example Corp.\n"),
+        ];
+        for year in [
+            "current-year-for-modified-files",
+            "range-end-is-commit-year",
+        ] {
+            let pipeline = policy(year);
+            let mut files = files.to_vec();
+            files.push(("pipeline.yaml", &pipeline));
+            let dir = workspace(&files);
+            let report = detector(&dir).scan().unwrap();
+            assert_eq!(reasons(&report), vec![], "{}", year);
+            // the files changed since `--base` are checked
+            let changes = [Change::new("old.js", ChangeKind::Modified)];
+            let report = detector(&dir).scan_changes(&changes).unwrap();
+            assert_eq!(reasons(&report), vec![("old.js", Reason::OutdatedYear)]);
+        }
+    }
 }
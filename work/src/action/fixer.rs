@@ -0,0 +1,248 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::borrow::Cow;
+use std::path::Path;
+
+/// A change which inserts the header, written as a review suggestion: `text`
+/// replaces the 1-based `line` of the file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Suggestion {
+    pub line: usize,
+    pub text: String,
+}
+
+/// Insert `header` at the top of the file, after any preamble which has to stay first.
+/// The BOM, encoding and line endings of the file are kept as they are.
+pub fn insert_header(file: &Path, header: &str) -> anyhow::Result<()> {
+    let bytes = std::fs::read(file)?;
+    let (encoding, bom) = match Encoding::for_bom(&bytes) {
+        Some((encoding, length)) => (encoding, &bytes[..length]),
+        None => (UTF_8, &bytes[..0]),
+    };
+    let text = decode(&bytes[bom.len()..], encoding)?;
+    let eol = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let offset = insertion_point(&text);
+    let mut content = String::with_capacity(text.len() + header.len());
+    content.push_str(&text[..offset]);
+    if offset > 0 && !content.ends_with('\n') {
+        content.push_str(eol);
+    }
+    for line in header.lines() {
+        content.push_str(line);
+        content.push_str(eol);
+    }
+    content.push_str(&text[offset..]);
+
+    let mut output = bom.to_vec();
+    output.extend(encode(&content, encoding));
+    std::fs::write(file, output)?;
+    Ok(())
+}
+
+/// The same insertion as `insert_header` does, as a suggestion on the line in front of
+/// which the header goes. There is nothing to anchor a suggestion at in an empty file.
+pub fn suggest_header(file: &Path, header: &str) -> anyhow::Result<Option<Suggestion>> {
+    let bytes = std::fs::read(file)?;
+    let (encoding, bom_length) = Encoding::for_bom(&bytes).unwrap_or((UTF_8, 0));
+    let text = decode(&bytes[bom_length..], encoding)?;
+    let offset = insertion_point(&text);
+    let line = text[..offset].matches('\n').count();
+    let lines: Vec<&str> = text.lines().collect();
+    let suggestion = match lines.get(line) {
+        // the header is inserted in front of the line
+        Some(x) => Suggestion {
+            line: line + 1,
+            text: format!("{}{}", header, x),
+        },
+        // the file ends with its preamble, the header is appended to the last line
+        None if !lines.is_empty() => Suggestion {
+            line: lines.len(),
+            text: format!("{}\n{}", lines[lines.len() - 1], header.trim_end()),
+        },
+        None => return Ok(None),
+    };
+    Ok(Some(suggestion))
+}
+
+fn decode<'a>(bytes: &'a [u8], encoding: &'static Encoding) -> anyhow::Result<Cow<'a, str>> {
+    let (text, malformed) = encoding.decode_without_bom_handling(bytes);
+    if malformed {
+        return Err(anyhow::anyhow!("fail to decode file content"));
+    }
+    Ok(text)
+}
+
+/// Byte offset where the header goes: behind a shebang, a python encoding declaration,
+/// an XML prolog or doctype, `@echo off`, `#region` lines and "use strict" directives
+/// at the top of the file.
+fn insertion_point(text: &str) -> usize {
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let preamble = (offset == 0 && trimmed.starts_with("#!"))
+            || (trimmed.starts_with("# -*-") && trimmed.contains("coding"))
+            || trimmed.starts_with("<?xml")
+            || trimmed.to_lowercase().starts_with("<!doctype")
+            || trimmed.to_lowercase().starts_with("@echo off")
+            || trimmed.starts_with("#region")
+            || ["\"use strict\"", "'use strict'"].contains(&trimmed.trim_end_matches(';'));
+        if !preamble {
+            break;
+        }
+        offset += line.len();
+    }
+    offset
+}
+
+// encoding_rs only encodes into UTF-8 for UTF-16 encodings, UTF-16 is written by hand
+fn encode(text: &str, encoding: &'static Encoding) -> Vec<u8> {
+    if encoding == UTF_16LE {
+        text.encode_utf16().flat_map(|x| x.to_le_bytes()).collect()
+    } else if encoding == UTF_16BE {
+        text.encode_utf16().flat_map(|x| x.to_be_bytes()).collect()
+    } else {
+        encoding.encode(text).0.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Detector;
+
+    const HEADER: &str = "// Copyright Example Corp.\n";
+
+    /// Content of a file after the header is inserted into it.
+    fn fixed(name: &str, content: &[u8]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        insert_header(&path, HEADER).unwrap();
+        std::fs::read(&path).unwrap()
+    }
+
+    #[test]
+    fn header_goes_on_top() {
+        assert_eq!(
+            fixed("a.go", b"package a\n"),
+            b"// Copyright Example Corp.\npackage a\n"
+        );
+        assert_eq!(fixed("a.go", b""), b"// Copyright Example Corp.\n");
+    }
+
+    #[test]
+    fn bom_is_kept() {
+        assert_eq!(
+            fixed("a.cs", b"\xef\xbb\xbfclass A {}\n"),
+            b"\xef\xbb\xbf// Copyright Example Corp.\nclass A {}\n"
+        );
+        let utf16: Vec<u8> = "\u{feff}class A {}\n"
+            .encode_utf16()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let expected: Vec<u8> = "\u{feff}// Copyright Example Corp.\nclass A {}\n"
+            .encode_utf16()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        assert_eq!(fixed("a.cs", &utf16), expected);
+    }
+
+    #[test]
+    fn crlf_is_kept() {
+        assert_eq!(
+            fixed("a.cs", b"class A {}\r\n"),
+            b"// Copyright Example Corp.\r\nclass A {}\r\n"
+        );
+    }
+
+    #[test]
+    fn header_goes_behind_preamble() {
+        assert_eq!(
+            fixed("a.sh", b"#!/bin/sh\necho a\n"),
+            b"#!/bin/sh\n// Copyright Example Corp.\necho a\n"
+        );
+        assert_eq!(
+            fixed("a.py", b"#!/usr/bin/env python\n# -*- coding: utf-8 -*-\nimport os\n"),
+            b"#!/usr/bin/env python\n# -*- coding: utf-8 -*-\n// Copyright Example Corp.\nimport os\n"
+        );
+        assert_eq!(
+            fixed("a.xml", b"<?xml version=\"1.0\"?>\r\n<a/>\r\n"),
+            b"<?xml version=\"1.0\"?>\r\n// Copyright Example Corp.\r\n<a/>\r\n"
+        );
+        assert_eq!(
+            fixed("a.bat", b"@ECHO OFF\nset A=1\n"),
+            b"@ECHO OFF\n// Copyright Example Corp.\nset A=1\n"
+        );
+        assert_eq!(
+            fixed("a.js", b"'use strict';\nvar a;\n"),
+            b"'use strict';\n// Copyright Example Corp.\nvar a;\n"
+        );
+        // a shebang which isn't the first line is no preamble
+        assert_eq!(
+            fixed("a.sh", b"echo a\n#!/bin/sh\n"),
+            b"// Copyright Example Corp.\necho a\n#!/bin/sh\n"
+        );
+    }
+
+    #[test]
+    fn file_ending_with_preamble_gets_a_line_break() {
+        assert_eq!(
+            fixed("a.sh", b"#!/bin/sh"),
+            b"#!/bin/sh\n// Copyright Example Corp.\n"
+        );
+    }
+
+    #[test]
+    fn undecodable_file_is_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.cs");
+        std::fs::write(&path, b"class \xff {}\n").unwrap();
+        assert!(insert_header(&path, HEADER).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"class \xff {}\n");
+    }
+
+    #[test]
+    fn suggestion_replaces_the_line_behind_preamble() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.sh");
+        std::fs::write(&path, "#!/bin/sh\r\necho a\r\n").unwrap();
+        assert_eq!(
+            suggest_header(&path, HEADER).unwrap(),
+            Some(Suggestion {
+                line: 2,
+                text: "// Copyright Example Corp.\necho a".to_string(),
+            })
+        );
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        assert_eq!(
+            suggest_header(&path, HEADER).unwrap(),
+            Some(Suggestion {
+                line: 1,
+                text: "#!/bin/sh\n// Copyright Example Corp.".to_string(),
+            })
+        );
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(suggest_header(&path, HEADER).unwrap(), None);
+    }
+
+    #[test]
+    fn fixing_twice_inserts_one_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.cs");
+        std::fs::write(&path, "\u{feff}using System;\r\n").unwrap();
+        for expected in [vec!["a.cs".to_string()], vec![]] {
+            let detector = Detector::new(dir.path()).unwrap();
+            let report = detector.scan().unwrap();
+            assert_eq!(detector.fix(&report.findings), expected);
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.matches("Copyright").count(), 1);
+        assert!(content.starts_with("\u{feff}/*\r\n * Copyright"));
+        assert!(content.ends_with(" */\r\nusing System;\r\n"));
+        assert!(Detector::new(dir.path())
+            .unwrap()
+            .scan()
+            .unwrap()
+            .findings
+            .is_empty());
+    }
+}
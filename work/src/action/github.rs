@@ -1,26 +1,83 @@
 use crate::action::models::{
-    GithubPayload, GithubPullRequestPayload, GITHUB_ADD_COMMENT, GITHUB_DELETE_COMMENT,
-    GITHUB_QUERY, BAD_COMMENT,
+    AddComment, CommentVariables, CommentsQuery, FilesQuery, GithubCheckRunPayload, GithubPayload,
+    GithubQuery, NodeVariables, PageInfo, PullRequestQuery, PullRequestVariables, ResolveThread,
+    ThreadsQuery, UpdateComment,
+};
+use crate::action::{
+    conclude, file_comment_path, git_fetch, is_report_comment, reconcile_file_comments,
+    render_file_comment, render_report_comment, render_summary, scan, ApiError, BuildState, Change,
+    ChangeKind, FileComment, Handler, Options, Report, CHECK_NAME,
 };
-use crate::action::{git_fetch, scan, Handler};
 use reqwest::blocking::{Client, Response};
-use serde::Deserialize;
 use serde::Serialize;
-use std::ops::Deref;
+use serde_json::json;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+// the Checks API accepts at most 50 annotations per request
+const ANNOTATIONS_PER_REQUEST: usize = 50;
+// the files of a pull-request are listed up to this count, the rest cannot be fetched
+const LISTED_FILES_LIMIT: u32 = 3000;
+// context of the commit status, branch protection tells it apart from the check run
+const STATUS_CONTEXT: &str = "copyright/status";
 
 pub struct Github {
     client: Client,
+    endpoints: Endpoints,
 }
-#[derive(Debug, Serialize, Deserialize)]
-struct GraphqlQuery {
-    query: String,
+
+/// URLs of github.com or of a GitHub Enterprise Server.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub api: String,
+    pub graphql: String,
+    /// `{project}` and `{repository}` are replaced with the repository
+    pub clone: String,
+}
+
+impl Endpoints {
+    /// The GraphQL and clone URLs are derived from the REST URL when they are omitted,
+    /// e.g. `https://ghes.example.com/api/v3` serves `https://ghes.example.com/api/graphql`.
+    pub fn new(api: &str, graphql: Option<String>, clone: Option<String>) -> Endpoints {
+        let api = api.trim_end_matches('/');
+        let site = match api.strip_suffix("/api/v3") {
+            Some(site) => site,
+            None if api == "https://api.github.com" => "https://github.com",
+            None => api,
+        };
+        let graphql = graphql.unwrap_or_else(|| match api.strip_suffix("/v3") {
+            Some(base) => format!("{}/graphql", base),
+            None => format!("{}/graphql", api),
+        });
+        let clone = clone.unwrap_or_else(|| format!("{}/{{project}}/{{repository}}.git", site));
+        Endpoints {
+            api: api.to_string(),
+            graphql,
+            clone,
+        }
+    }
+
+    fn clone_url(&self, project: &str, repository: &str) -> String {
+        self.clone
+            .replace("{project}", project)
+            .replace("{repository}", repository)
+    }
+}
+#[derive(Debug, Serialize)]
+struct GraphqlRequest<'a, V> {
+    query: &'a str,
+    variables: &'a V,
 }
 
 #[derive(Debug)]
 struct PullRequest {
     id: String,
+    head_ref: String,
+    head_sha: String,
+    cross_repository: bool,
+    changed_files: u32,
     comment: Option<Comment>,
-    files: Vec<String>,
+    files: Vec<Change>,
 }
 
 #[derive(Debug)]
@@ -38,7 +95,7 @@ impl Clone for Comment {
 }
 
 impl Github {
-    pub fn new() -> Github {
+    pub fn new(endpoints: Endpoints) -> Github {
         let token = std::env::var("GIT_PASSWORD").expect("GIT_PASSWORD is not set");
         let mut header = reqwest::header::HeaderMap::new();
         header.insert(
@@ -60,154 +117,626 @@ impl Github {
             .unwrap();
         Github {
             client: client.to_owned(),
+            endpoints,
         }
     }
 
     fn get_pull_request(&self, project: &str, repository: &str, id: u32) -> anyhow::Result<PullRequest> {
-        let mut template = tera::Tera::default();
-        let mut files: Vec<String> = Vec::new();
-        let mut comments: Vec<Comment> = Vec::new();
-        let mut file_after: String = "".to_string();
-        let mut comment_after: String = "".to_string();
-        let mut pull_request_node: String = "".to_string();
-        loop {
-            let mut context = tera::Context::new();
-            context.insert("file_after", &file_after);
-            context.insert("comment_after", &comment_after);
-            context.insert("project", project);
-            context.insert("repository", repository);
-            context.insert("number", &format!("{}", id));
-            let body = template.render_str(GITHUB_QUERY, &context)?;
-            let resp: Response = self
-                .client
-                .post("https://api.github.com/graphql")
-                .json(&GraphqlQuery { query: body })
-                .send()
-                .unwrap();
-            if resp.status().is_success() {
-                let payload: GithubPayload = resp.json().unwrap();
-                if let Some(errors) = payload.errors {
-                    return Err(anyhow::anyhow!(errors.first().unwrap().message.clone()));
-                }
-                let data: GithubPullRequestPayload = serde_json::from_value(payload.data).unwrap();
-                if pull_request_node.is_empty() {
-                    pull_request_node = data.repository.pull_request.id;
-                }
-                data.repository
-                    .pull_request
-                    .files
+        let variables = PullRequestVariables {
+            owner: project.to_string(),
+            repository: repository.to_string(),
+            number: id,
+            after: None,
+        };
+        let data = self.graphql::<PullRequestQuery>(&variables)?;
+        let pull_request = data.repository.pull_request;
+        let files = self.get_files(&variables)?;
+        let comment = self
+            .get_comments(&variables)?
+            .into_iter()
+            .find(|x| is_report_comment(&x.body));
+        Ok(PullRequest {
+            id: pull_request.id,
+            head_ref: pull_request.head_ref_name,
+            head_sha: pull_request.head_ref_oid,
+            cross_repository: pull_request.is_cross_repository,
+            changed_files: pull_request.changed_files,
+            files,
+            comment,
+        })
+    }
+
+    /// Page the changed files, a file seen again on a later page is dropped.
+    fn get_files(&self, variables: &PullRequestVariables) -> anyhow::Result<Vec<Change>> {
+        let mut variables = variables.clone();
+        page_connection(
+            |after| {
+                variables.after = after;
+                let data = self.graphql::<FilesQuery>(&variables)?;
+                let connection = data.repository.pull_request.files;
+                let files = connection
                     .edges
                     .iter()
-                    .map(|x| x.node.path.deref())
-                    .for_each(|x| files.push(x.to_string()));
-                data.repository
-                    .pull_request
-                    .comments
+                    .map(|x| Change::new(&x.node.path, change_kind(&x.node.change_type)))
+                    .collect();
+                Ok((files, connection.page_info))
+            },
+            |x: &Change| x.path.to_string(),
+        )
+    }
+
+    /// Page the comments, a comment seen again on a later page is dropped.
+    fn get_comments(&self, variables: &PullRequestVariables) -> anyhow::Result<Vec<Comment>> {
+        let mut variables = variables.clone();
+        page_connection(
+            |after| {
+                variables.after = after;
+                let data = self.graphql::<CommentsQuery>(&variables)?;
+                let connection = data.repository.pull_request.comments;
+                let comments = connection
                     .edges
                     .iter()
                     .map(|x| Comment {
                         id: x.node.id.to_string(),
                         body: x.node.body.to_string(),
                     })
-                    .for_each(|x| comments.push(x));
-                if !data.repository.pull_request.files.page_info.has_next_page
-                    && !data
-                        .repository
-                        .pull_request
-                        .comments
-                        .page_info
-                        .has_next_page
-                {
-                    break;
-                }
-                if let Some(next) = data.repository.pull_request.files.page_info.end_cursor {
-                    file_after = next;
-                }
-                if let Some(next) = data.repository.pull_request.comments.page_info.end_cursor {
-                    comment_after = next;
-                }
-            } else {
-                return Err(anyhow::anyhow!(resp.status().to_string()));
+                    .collect();
+                Ok((comments, connection.page_info))
+            },
+            |x: &Comment| x.id.to_string(),
+        )
+    }
+
+    /// Start a check run on the head commit of the pull-request, its id is returned.
+    /// The Checks API accepts tokens of a GitHub App only.
+    fn create_check_run(&self, project: &str, repository: &str, sha: &str) -> anyhow::Result<u64> {
+        let url = format!(
+            "{}/repos/{}/{}/check-runs",
+            self.endpoints.api, project, repository
+        );
+        let body = json!({
+            "name": CHECK_NAME,
+            "head_sha": sha,
+            "status": "in_progress",
+        });
+        let resp = self.client.post(&url).json(&body).send()?;
+        if resp.status().is_success() {
+            let payload: GithubCheckRunPayload = resp.json()?;
+            Ok(payload.id)
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
+    }
+
+    /// Complete the check run with the result of the scan, or with a failure when
+    /// the scan didn't finish. Annotations are sent in batches, GitHub appends them.
+    fn complete_check_run(
+        &self,
+        project: &str,
+        repository: &str,
+        check_id: u64,
+        result: &anyhow::Result<Report>,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/repos/{}/{}/check-runs/{}",
+            self.endpoints.api, project, repository, check_id
+        );
+        let (state, title) = conclude(result);
+        let conclusion = match state {
+            BuildState::Successful => "success",
+            BuildState::Cancelled => "cancelled",
+            _ => "failure",
+        };
+        let (summary, annotations) = match result {
+            Ok(report) => (
+                render_summary(report),
+                report
+                    .findings
+                    .iter()
+                    .map(|x| {
+                        json!({
+                            "path": x.path,
+                            "start_line": x.lines.0,
+                            "end_line": x.lines.1,
+                            "annotation_level": "failure",
+                            "title": CHECK_NAME,
+                            "message": x.reason.to_string(),
+                        })
+                    })
+                    .collect(),
+            ),
+            Err(err) => (format!("```\n{:#}\n```", err), vec![]),
+        };
+        let mut batches: Vec<&[serde_json::Value]> =
+            annotations.chunks(ANNOTATIONS_PER_REQUEST).collect();
+        if batches.is_empty() {
+            batches.push(&[]);
+        }
+        for (idx, batch) in batches.iter().enumerate() {
+            let mut body = json!({
+                "output": {
+                    "title": title,
+                    "summary": summary,
+                    "annotations": batch,
+                },
+            });
+            // the run is completed with the last batch, so it's never seen half annotated
+            if idx + 1 == batches.len() {
+                body["status"] = json!("completed");
+                body["conclusion"] = json!(conclusion);
+            }
+            let resp = self.client.patch(&url).json(&body).send()?;
+            if !resp.status().is_success() {
+                return Err(ApiError(resp.status().to_string()).into());
             }
         }
-        let comment_ptr = comments.iter().find(|x| x.body == BAD_COMMENT);
-        let comment = match comment_ptr {
-            Some(x) => Some(x.clone()),
-            None => None,
+        Ok(())
+    }
+
+    /// Set the commit status of the scan, the state is mapped to the GitHub one.
+    fn set_status(
+        &self,
+        project: &str,
+        repository: &str,
+        sha: &str,
+        state: BuildState,
+        description: &str,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/repos/{}/{}/statuses/{}",
+            self.endpoints.api, project, repository, sha
+        );
+        let state = match state {
+            BuildState::InProgress => "pending",
+            BuildState::Successful => "success",
+            BuildState::Failed => "failure",
+            BuildState::Cancelled => "error",
         };
-        Ok(PullRequest {
-            id: pull_request_node,
-            files,
-            comment,
-        })
+        let body = json!({
+            "state": state,
+            "context": STATUS_CONTEXT,
+            "description": description,
+        });
+        let resp = self.client.post(&url).json(&body).send()?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(ApiError(resp.status().to_string()).into())
+        }
     }
-    fn delete_comment(&self, id: &str) -> anyhow::Result<()> {
-        let mut template = tera::Tera::default();
-        let mut context = tera::Context::new();
-        context.insert("id", id);
-        let body = template.render_str(GITHUB_DELETE_COMMENT, &context)?;
-        let resp: Response = self
-            .client
-            .post("https://api.github.com/graphql")
-            .json(&GraphqlQuery { query: body })
-            .send()
-            .unwrap();
+
+    /// Review threads started by an inline comment of the bot.
+    fn get_review_threads(
+        &self,
+        project: &str,
+        repository: &str,
+        id: u32,
+    ) -> anyhow::Result<Vec<FileComment<String>>> {
+        let mut variables = PullRequestVariables {
+            owner: project.to_string(),
+            repository: repository.to_string(),
+            number: id,
+            after: None,
+        };
+        page_connection(
+            |after| {
+                variables.after = after;
+                let data = self.graphql::<ThreadsQuery>(&variables)?;
+                let connection = data.repository.pull_request.review_threads;
+                let mut threads = vec![];
+                for edge in connection.edges.iter() {
+                    let path = edge
+                        .node
+                        .comments
+                        .nodes
+                        .first()
+                        .and_then(|x| file_comment_path(&x.body));
+                    if let Some(path) = path {
+                        threads.push(FileComment {
+                            id: edge.node.id.to_string(),
+                            path: path.to_string(),
+                            open: !edge.node.is_resolved,
+                            outdated: edge.node.is_outdated,
+                        });
+                    }
+                }
+                Ok((threads, connection.page_info))
+            },
+            |x: &FileComment<String>| x.id.to_string(),
+        )
+    }
+
+    fn resolve_thread(&self, id: &str) -> anyhow::Result<()> {
+        let variables = NodeVariables { id: id.to_string() };
+        self.graphql::<ResolveThread>(&variables)?;
+        Ok(())
+    }
+
+    /// Comment on every offending file in a review, the threads of earlier runs are
+    /// reconciled with the report.
+    fn review(
+        &self,
+        project: &str,
+        repository: &str,
+        id: u32,
+        commit: &str,
+        files: &[Change],
+        report: &Report,
+    ) -> anyhow::Result<()> {
+        let threads = self.get_review_threads(project, repository, id)?;
+        let (stale, new) = reconcile_file_comments(threads, files, report);
+        for thread in stale {
+            tracing::info!("resolve review thread on {}", thread.path);
+            self.resolve_thread(&thread.id)?;
+        }
+        let comments: Vec<serde_json::Value> = new
+            .iter()
+            .map(|x| match x.line {
+                Some(line) => json!({
+                    "path": x.finding.path,
+                    "line": line,
+                    "side": "RIGHT",
+                    "body": render_file_comment(x.finding, true),
+                }),
+                None => json!({
+                    "path": x.finding.path,
+                    "subject_type": "file",
+                    "body": render_file_comment(x.finding, false),
+                }),
+            })
+            .collect();
+        if comments.is_empty() {
+            return Ok(());
+        }
+        tracing::info!("review {} file(s) in pull-request", comments.len());
+        let url = format!(
+            "{}/repos/{}/{}/pulls/{}/reviews",
+            self.endpoints.api, project, repository, id
+        );
+        let body = json!({
+            "commit_id": commit,
+            "event": "COMMENT",
+            "body": format!("{} file(s) violate the copyright policy", comments.len()),
+            "comments": comments,
+        });
+        let resp = self.client.post(&url).json(&body).send()?;
         if resp.status().is_success() {
-            let payload: GithubPayload = resp.json().unwrap();
-            if let Some(errors) = payload.errors {
-                return Err(anyhow::anyhow!(errors.first().unwrap().message.clone()));
-            }
+            Ok(())
         } else {
-            return Err(anyhow::anyhow!(resp.status().to_string()));
+            Err(ApiError(resp.status().to_string()).into())
         }
+    }
+
+    fn update_comment(&self, id: &str, message: &str) -> anyhow::Result<()> {
+        let variables = CommentVariables {
+            id: id.to_string(),
+            body: message.to_string(),
+        };
+        self.graphql::<UpdateComment>(&variables)?;
+        Ok(())
+    }
+    fn create_comment(&self, id: &str, message: &str) -> anyhow::Result<()> {
+        let variables = CommentVariables {
+            id: id.to_string(),
+            body: message.to_string(),
+        };
+        self.graphql::<AddComment>(&variables)?;
         Ok(())
     }
-    fn create_comment(&self, id: &str) -> anyhow::Result<()> {
-        let mut template = tera::Tera::default();
-        let mut context = tera::Context::new();
-        context.insert("id", id);
-        context.insert("body", BAD_COMMENT);
-        let body = template.render_str(GITHUB_ADD_COMMENT, &context)?;
+
+    /// Send a GraphQL document with its variables, the first error of the response fails it.
+    fn graphql<Q: GithubQuery>(&self, variables: &Q::Variables) -> anyhow::Result<Q::Data> {
         let resp: Response = self
             .client
-            .post("https://api.github.com/graphql")
-            .json(&GraphqlQuery { query: body })
-            .send()
-            .unwrap();
-        if resp.status().is_success() {
-            let payload: GithubPayload = resp.json().unwrap();
-            if let Some(errors) = payload.errors {
-                return Err(anyhow::anyhow!(errors.first().unwrap().message.clone()));
+            .post(&self.endpoints.graphql)
+            .json(&GraphqlRequest {
+                query: Q::DOCUMENT,
+                variables,
+            })
+            .send()?;
+        if !resp.status().is_success() {
+            return Err(ApiError(resp.status().to_string()).into());
+        }
+        let payload: GithubPayload = resp.json()?;
+        if let Some(errors) = payload.errors {
+            return Err(ApiError(errors.first().unwrap().message.clone()).into());
+        }
+        Ok(serde_json::from_value(payload.data)?)
+    }
+}
+
+/// Every node of a connection which is paged by its cursor, `fetch` gets the cursor
+/// to start after and hands back a page. A node seen again on a later page is dropped
+/// by its `key`, a cursor which doesn't move on fails instead of looping forever.
+fn page_connection<N, K: Eq + Hash>(
+    mut fetch: impl FnMut(Option<String>) -> anyhow::Result<(Vec<N>, PageInfo)>,
+    key: impl Fn(&N) -> K,
+) -> anyhow::Result<Vec<N>> {
+    let mut nodes: Vec<N> = Vec::new();
+    let mut seen: HashSet<K> = HashSet::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let (page, info) = fetch(cursor.clone())?;
+        for node in page {
+            if seen.insert(key(&node)) {
+                nodes.push(node);
             }
-        } else {
-            return Err(anyhow::anyhow!(resp.status().to_string()));
         }
-        Ok(())
+        match info.end_cursor {
+            Some(next) if info.has_next_page && cursor.as_ref() == Some(&next) => {
+                return Err(ApiError(format!("cursor {} of a page is repeated", next)).into())
+            }
+            Some(next) if info.has_next_page => cursor = Some(next),
+            _ => return Ok(nodes),
+        }
+    }
+}
+
+/// A partial list of files would pass a pull-request which is not checked completely.
+fn check_listed_files(pull_request: &PullRequest) -> anyhow::Result<()> {
+    let listed = pull_request.files.len() as u32;
+    if listed < pull_request.changed_files {
+        let reason = match pull_request.changed_files > LISTED_FILES_LIMIT {
+            true => format!("GitHub lists at most {} files", LISTED_FILES_LIMIT),
+            false => "the list is incomplete".to_string(),
+        };
+        return Err(anyhow::anyhow!(
+            "pull-request changes {} files but only {} are listed, {}; split the pull-request or run `work scan --base` in the pipeline",
+            pull_request.changed_files,
+            listed,
+            reason
+        ));
+    }
+    Ok(())
+}
+
+/// Kind of a `PatchStatus`, GitHub doesn't tell the path a file is renamed from.
+fn change_kind(change_type: &str) -> ChangeKind {
+    match change_type {
+        "ADDED" | "COPIED" => ChangeKind::Added,
+        "DELETED" => ChangeKind::Deleted,
+        "RENAMED" => ChangeKind::Renamed,
+        _ => ChangeKind::Modified,
     }
 }
 
 impl Handler for Github {
-    fn execute(&mut self, project: &str, repository: &str, id: u32) -> anyhow::Result<()> {
+    fn execute(
+        &mut self,
+        project: &str,
+        repository: &str,
+        id: u32,
+        options: &Options,
+    ) -> anyhow::Result<Report> {
         let pull_request = self.get_pull_request(project, repository, id)?;
-        git_fetch(
-            &pull_request.files,
-            &format!(
-                "https://github.com/{project}/{repo}.git",
-                project = project,
-                repo = repository
-            ),
+        let commit = options.commit.as_deref().unwrap_or(&pull_request.head_sha);
+        self.set_status(
+            project,
+            repository,
+            commit,
+            BuildState::InProgress,
+            "copyright check is running",
+        )?;
+        // the commit status is concluded even when the Checks API fails
+        let check_id = self.create_check_run(project, repository, commit);
+        let result = check_listed_files(&pull_request)
+            .and_then(|_| {
+                git_fetch(
+                    &pull_request.files,
+                    &self.endpoints.clone_url(project, repository),
+                    &format!("refs/pull/{}/head", id),
+                    id,
+                )
+            })
+            .and_then(|_| {
+                let branch = match options.fix {
+                    true if pull_request.cross_repository => {
+                        tracing::warn!("cannot push fixes to a pull-request from fork");
+                        None
+                    }
+                    true => Some(pull_request.head_ref.as_str()),
+                    false => None,
+                };
+                scan(&pull_request.files, branch)
+            });
+        let (state, description) = conclude(&result);
+        self.set_status(project, repository, commit, state, &description)?;
+        let check_id = check_id?;
+        tracing::info!("complete check run {}", check_id);
+        self.complete_check_run(project, repository, check_id, &result)?;
+        let report = result?;
+        self.review(
+            project,
+            repository,
             id,
+            commit,
+            &pull_request.files,
+            &report,
         )?;
-        let yes = scan()?;
-        if yes && pull_request.comment.is_none() {
-            tracing::info!("report comment to pull-request");
-            self.create_comment(&pull_request.id)?;
-        } else if !yes && pull_request.comment.is_some() {
-            tracing::info!("remove comment from pull-request");
-            self.delete_comment(&pull_request.comment.unwrap().id)?;
-        } else {
-            tracing::info!("keep comment in pull-request");
+        let previous = pull_request.comment.as_ref().map(|x| x.body.as_str());
+        let message = render_report_comment(&report, commit, previous);
+        match &pull_request.comment {
+            Some(comment) => {
+                tracing::info!("update comment in pull-request");
+                self.update_comment(&comment.id, &message)?;
+            }
+            None if !report.findings.is_empty() => {
+                tracing::info!("report comment to pull-request");
+                self.create_comment(&pull_request.id, &message)?;
+            }
+            None => tracing::info!("nothing to report to pull-request"),
         }
-        Ok(())
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{fake, REPORT_COMMENT_MARKER};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+
+    fn page(
+        nodes: &[&str],
+        end_cursor: Option<&str>,
+        has_next_page: bool,
+    ) -> (Vec<String>, PageInfo) {
+        (
+            nodes.iter().map(|x| x.to_string()).collect(),
+            PageInfo {
+                end_cursor: end_cursor.map(|x| x.to_string()),
+                has_next_page,
+            },
+        )
+    }
+
+    fn pull_request(listed: usize, changed_files: u32) -> PullRequest {
+        PullRequest {
+            id: "PR_1".to_string(),
+            head_ref: "feature".to_string(),
+            head_sha: "abc".to_string(),
+            cross_repository: false,
+            changed_files,
+            comment: None,
+            files: (0..listed)
+                .map(|x| Change::new(&format!("src/{}.rs", x), ChangeKind::Modified))
+                .collect(),
+        }
+    }
+
+    /// GraphQL API of the fake, the files come in two pages and the second comment is
+    /// the report.
+    async fn graphql(
+        headers: HeaderMap,
+        Json(request): Json<Value>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let bearer = headers.get("Authorization").and_then(|x| x.to_str().ok());
+        if bearer != Some("Bearer secret") {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        let query = request["query"].as_str().unwrap_or_default();
+        let pull_request = if query.contains("files(") {
+            let (path, change_type, next) = match request["variables"]["after"].as_str() {
+                None => ("src/a.rs", "ADDED", true),
+                Some(_) => ("src/b.rs", "RENAMED", false),
+            };
+            json!({"files": {
+                "edges": [{"node": {"path": path, "changeType": change_type}}],
+                "pageInfo": {"endCursor": "1", "hasNextPage": next},
+            }})
+        } else if query.contains("comments(") {
+            json!({"comments": {
+                "edges": [
+                    {"node": {"id": "C_1", "body": "looks good"}},
+                    {"node": {"id": "C_2", "body": format!("{}\nreport", REPORT_COMMENT_MARKER)}},
+                ],
+                "pageInfo": {"endCursor": null, "hasNextPage": false},
+            }})
+        } else {
+            json!({
+                "id": "PR_1",
+                "headRefName": "feature",
+                "headRefOid": "abc",
+                "isCrossRepository": false,
+                "changedFiles": 2,
+            })
+        };
+        let data = json!({"repository": {"pullRequest": pull_request}});
+        Ok(Json(json!({ "data": data })))
+    }
+
+    #[test]
+    fn pull_request_is_read_from_enterprise_server() {
+        fake::credentials();
+        let url = fake::serve(Router::new().route("/api/graphql", post(graphql)));
+        let github = Github::new(Endpoints::new(&format!("{}/api/v3", url), None, None));
+        let pull_request = github.get_pull_request("octo", "hello", 1).unwrap();
+        assert_eq!(pull_request.id, "PR_1");
+        assert_eq!(pull_request.head_sha, "abc");
+        assert_eq!(
+            pull_request.files,
+            vec![
+                Change::new("src/a.rs", ChangeKind::Added),
+                Change::new("src/b.rs", ChangeKind::Renamed),
+            ]
+        );
+        assert_eq!(pull_request.comment.as_ref().unwrap().id, "C_2");
+        assert!(check_listed_files(&pull_request).is_ok());
+        assert_eq!(
+            github.endpoints.clone_url("octo", "hello"),
+            format!("{}/octo/hello.git", url)
+        );
+    }
+
+    #[test]
+    fn pages_are_followed_by_cursor() {
+        let mut cursors = vec![];
+        let nodes = page_connection(
+            |after| {
+                cursors.push(after.clone());
+                Ok(match after.as_deref() {
+                    None => page(&["a", "b"], Some("1"), true),
+                    Some("1") => page(&["b", "c"], Some("2"), true),
+                    _ => page(&["d"], Some("3"), false),
+                })
+            },
+            |x: &String| x.to_string(),
+        )
+        .unwrap();
+        assert_eq!(nodes, vec!["a", "b", "c", "d"]);
+        assert_eq!(
+            cursors,
+            vec![None, Some("1".to_string()), Some("2".to_string())]
+        );
+    }
+
+    #[test]
+    fn paging_stops_without_cursor() {
+        let mut calls = 0;
+        let nodes = page_connection(
+            |_| {
+                calls += 1;
+                Ok(page(&["a"], None, true))
+            },
+            |x: &String| x.to_string(),
+        )
+        .unwrap();
+        assert_eq!(nodes, vec!["a"]);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn repeated_cursor_fails_paging() {
+        let result = page_connection(
+            |_| Ok(page(&["a"], Some("1"), true)),
+            |x: &String| x.to_string(),
+        );
+        assert!(result.unwrap_err().to_string().contains("repeated"));
+    }
+
+    #[test]
+    fn failed_page_fails_paging() {
+        let result = page_connection(
+            |after| match after {
+                None => Ok(page(&["a"], Some("1"), true)),
+                Some(_) => Err(ApiError("502 Bad Gateway".to_string()).into()),
+            },
+            |x: &String| x.to_string(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn listed_files_are_checked() {
+        assert!(check_listed_files(&pull_request(3, 3)).is_ok());
+        let error = check_listed_files(&pull_request(2, 3)).unwrap_err();
+        assert!(error.to_string().contains("the list is incomplete"));
+        let error = check_listed_files(&pull_request(3000, 3500)).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("changes 3500 files but only 3000 are listed"));
+        assert!(message.contains("GitHub lists at most 3000 files"));
     }
 }
@@ -0,0 +1,154 @@
+use crate::action::template::CommentStyle;
+use crate::action::{COPYRIGHT, COPYRIGHT_WEB};
+use std::path::Path;
+
+pub struct Language {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// files recognized by their whole name, e.g. `Dockerfile`
+    pub file_names: &'static [&'static str],
+    pub comment: CommentStyle,
+    pub template: &'static str,
+}
+
+/// Every language which is fetched for a pull-request and checked by the detector.
+pub static LANGUAGES: &[Language] = &[
+    Language {
+        name: "csharp",
+        extensions: &["cs"],
+        file_names: &[],
+        comment: CommentStyle::Block,
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "java",
+        extensions: &["java"],
+        file_names: &[],
+        comment: CommentStyle::Block,
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "javascript",
+        extensions: &["js", "jsx", "mjs", "cjs"],
+        file_names: &[],
+        comment: CommentStyle::Block,
+        template: COPYRIGHT_WEB,
+    },
+    Language {
+        name: "typescript",
+        extensions: &["ts", "tsx", "mts", "cts"],
+        file_names: &[],
+        comment: CommentStyle::Block,
+        template: COPYRIGHT_WEB,
+    },
+    Language {
+        name: "go",
+        extensions: &["go"],
+        file_names: &[],
+        comment: CommentStyle::Line("//"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "rust",
+        extensions: &["rs"],
+        file_names: &[],
+        comment: CommentStyle::Line("//"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "swift",
+        extensions: &["swift"],
+        file_names: &[],
+        comment: CommentStyle::Line("//"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "kotlin",
+        extensions: &["kt", "kts"],
+        file_names: &[],
+        comment: CommentStyle::Line("//"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "python",
+        extensions: &["py"],
+        file_names: &[],
+        comment: CommentStyle::Line("#"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "shell",
+        extensions: &["sh", "bash"],
+        file_names: &[],
+        comment: CommentStyle::Line("#"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "powershell",
+        extensions: &["ps1", "psm1", "psd1"],
+        file_names: &[],
+        comment: CommentStyle::Line("#"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "yaml",
+        extensions: &["yaml", "yml"],
+        file_names: &[],
+        comment: CommentStyle::Line("#"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "dockerfile",
+        extensions: &["dockerfile"],
+        file_names: &["Dockerfile"],
+        comment: CommentStyle::Line("#"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "xml",
+        extensions: &["xml", "csproj", "vbproj", "props", "targets", "config"],
+        file_names: &[],
+        comment: CommentStyle::Xml,
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "html",
+        extensions: &["html", "htm"],
+        file_names: &[],
+        comment: CommentStyle::Xml,
+        template: COPYRIGHT_WEB,
+    },
+    Language {
+        name: "sql",
+        extensions: &["sql"],
+        file_names: &[],
+        comment: CommentStyle::Line("--"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "lua",
+        extensions: &["lua"],
+        file_names: &[],
+        comment: CommentStyle::Line("--"),
+        template: COPYRIGHT,
+    },
+    Language {
+        name: "batch",
+        extensions: &["bat", "cmd"],
+        file_names: &[],
+        comment: CommentStyle::Rem,
+        template: COPYRIGHT,
+    },
+];
+
+/// Look up the language of a file by its name or extension.
+pub fn find(path: &Path) -> Option<&'static Language> {
+    let name = path.file_name()?.to_str()?;
+    if let Some(x) = LANGUAGES.iter().find(|x| x.file_names.contains(&name)) {
+        return Some(x);
+    }
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|x| x.extensions.contains(&ext.as_str()))
+}
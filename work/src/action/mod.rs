@@ -1,24 +1,160 @@
 pub mod bitbucket;
+pub mod bitbucket_cloud;
+mod config;
 mod detector;
+mod fixer;
 pub mod github;
+mod language;
 mod models;
+pub mod reporter;
+mod sarif;
+mod template;
 
-const SUPPORT_FILE_TYPES: &'static [&str] = &["cs", "java", "ts", "js"];
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+const FIX_COMMIT_MESSAGE: &str = "Add missing copyright headers";
+/// name of the status check which branch protection or a merge check can require
+const CHECK_NAME: &str = "copyright";
+// files listed in a summary, the SCMs limit the size of it
+const SUMMARY_FINDINGS_LIMIT: usize = 100;
+
+// set by SIGTERM, the webhook sends it when a newer commit supersedes the scan
+static CANCELLED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+#[derive(Default)]
+pub struct Options {
+    /// insert missing headers and push them to the source branch of the pull-request
+    pub fix: bool,
+    /// commit the status is reported on, the head of the pull-request when it's None
+    pub commit: Option<String>,
+}
+
+/// State of the scan as it's reported on a commit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BuildState {
+    InProgress,
+    Successful,
+    Failed,
+    Cancelled,
+}
+
+/// A request to the API of the SCM failed, `work` exits with its own code on it.
+#[derive(Debug)]
+pub struct ApiError(pub String);
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// The scan failed because of the SCM rather than the repository or the configuration.
+pub fn is_api_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|x| x.is::<ApiError>() || x.is::<reqwest::Error>())
+}
+
+/// The scan is stopped by SIGTERM.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "scan is cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Stop the scan on SIGTERM instead of dying with it: the next step fails, the scan is
+/// reported as cancelled and the workspace is removed. A second SIGTERM exits at once.
+pub fn handle_termination() -> anyhow::Result<()> {
+    let flag = CANCELLED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+    let signal = signal_hook::consts::SIGTERM;
+    // checked before the flag is set by the same signal
+    signal_hook::flag::register_conditional_shutdown(signal, 2, flag.clone())?;
+    signal_hook::flag::register(signal, flag.clone())?;
+    Ok(())
+}
+
+fn is_cancelled() -> bool {
+    CANCELLED.get().is_some_and(|x| x.load(Ordering::SeqCst))
+}
+
+/// Checked before every step which takes long.
+fn check_cancelled() -> anyhow::Result<()> {
+    match is_cancelled() {
+        true => Err(Cancelled.into()),
+        false => Ok(()),
+    }
+}
+
+/// Final state of a scan and a one-line description of it.
+fn conclude(result: &anyhow::Result<Report>) -> (BuildState, String) {
+    match result {
+        Ok(report) => describe(report),
+        // a git command which is terminated with `work` fails with its own error
+        Err(_) if is_cancelled() => (
+            BuildState::Cancelled,
+            "copyright check is cancelled".to_string(),
+        ),
+        Err(_) => (
+            BuildState::Failed,
+            "copyright check failed to run".to_string(),
+        ),
+    }
+}
+
+fn describe(report: &Report) -> (BuildState, String) {
+    if report.findings.is_empty() {
+        return (
+            BuildState::Successful,
+            "copyright headers are OK".to_string(),
+        );
+    }
+    (
+        BuildState::Failed,
+        format!(
+            "{} file(s) violate the copyright policy",
+            report.findings.len()
+        ),
+    )
+}
 
 pub trait Handler {
-    fn execute(&mut self, project: &str, repository: &str, id: u32) -> anyhow::Result<()>;
+    /// Scan the pull-request, report the result to it and hand back what was found
+    fn execute(
+        &mut self,
+        project: &str,
+        repository: &str,
+        id: u32,
+        options: &Options,
+    ) -> anyhow::Result<Report>;
 }
 
-pub use bitbucket::Bitbucket;
-pub use github::Github;
+pub use bitbucket::{Bitbucket, Endpoints as BitbucketEndpoints};
+pub use bitbucket_cloud::{BitbucketCloud, Endpoints as BitbucketCloudEndpoints};
+pub use detector::{Change, ChangeKind, Detector, Finding, Report};
+pub use github::{Endpoints as GithubEndpoints, Github};
 
 pub fn run_command(args: &[&str]) -> anyhow::Result<()> {
+    check_cancelled()?;
     println!("> git {}", args.join(" "));
-    std::process::Command::new("git").args(args).status()?;
+    let status = std::process::Command::new("git").args(args).status()?;
+    if !status.success() {
+        check_cancelled()?;
+        return Err(anyhow::anyhow!("'git {}' failed: {}", args[0], status));
+    }
     Ok(())
 }
 
-pub fn git_fetch(files: &[String], url: &str, id: u32) -> anyhow::Result<()> {
+/// Check out the changed files of the pull-request from `source`, a ref of the remote
+/// such as `refs/pull/{id}/head`, into the local branch `PR-{id}`.
+pub fn git_fetch(files: &[Change], url: &str, source: &str, id: u32) -> anyhow::Result<()> {
     run_command(&["remote", "add", "origin", url])?;
     run_command(&[
         "sparse-checkout",
@@ -27,14 +163,9 @@ pub fn git_fetch(files: &[String], url: &str, id: u32) -> anyhow::Result<()> {
         "pipeline.yaml",
         "pipeline.yml",
     ])?;
-    for file in files.iter() {
-        let ext = std::path::Path::new(file)
-            .extension()
-            .and_then(std::ffi::OsStr::to_str);
-        if let Some(e) = ext {
-            if SUPPORT_FILE_TYPES.contains(&e) {
-                run_command(&["sparse-checkout", "add", file])?;
-            }
+    for file in files.iter().filter(|x| x.kind != ChangeKind::Deleted) {
+        if language::find(std::path::Path::new(&file.path)).is_some() {
+            run_command(&["sparse-checkout", "add", &file.path])?;
         }
     }
     run_command(&[
@@ -42,16 +173,349 @@ pub fn git_fetch(files: &[String], url: &str, id: u32) -> anyhow::Result<()> {
         "--no-tags",
         "--depth=1",
         "origin",
-        &format!("+refs/pull/{id}/head:refs/remotes/origin/PR-{id}", id = id),
+        &format!("+{}:refs/remotes/origin/PR-{}", source, id),
     ])?;
     run_command(&["checkout", &format!("PR-{id}", id = id)])?;
+    // header templates referenced by pipeline.yaml are needed by the detector as well
+    let policy = config::load(&std::env::current_dir()?)?;
+    for template in policy.templates.iter() {
+        if let Some(file) = &template.file {
+            run_command(&["sparse-checkout", "add", file])?;
+        }
+    }
     Ok(())
 }
 
-pub static COPYRIGHT_CSHARP: &'static str = include_str!("copyright_csharp.txt");
-pub static COPYRIGHT_JS: &'static str = include_str!("copyright_js.txt");
+pub static COPYRIGHT: &str = include_str!("copyright.txt");
+pub static COPYRIGHT_WEB: &str = include_str!("copyright_web.txt");
+
+/// Files changed in the checkout at `root` since the git revision `base`.
+pub fn git_changes(root: &std::path::Path, base: &str) -> anyhow::Result<Vec<Change>> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["diff", "--name-status", "--relative", "-M", base])
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "'git diff' failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let mut changes = vec![];
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        // a rename or copy is "R<score>\t<from>\t<to>", others are "<status>\t<path>"
+        let change = match (fields[0].chars().next(), &fields[1..]) {
+            (Some('R'), [from, to]) => Change {
+                from: Some(from.to_string()),
+                ..Change::new(to, ChangeKind::Renamed)
+            },
+            (Some('C'), [_, to]) => Change::new(to, ChangeKind::Added),
+            (Some('A'), [path]) => Change::new(path, ChangeKind::Added),
+            (Some('D'), [path]) => Change::new(path, ChangeKind::Deleted),
+            (Some(_), [path]) => Change::new(path, ChangeKind::Modified),
+            _ => return Err(anyhow::anyhow!("unexpected 'git diff' output: {}", line)),
+        };
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// Scan the files changed by the checked out pull-request. When `branch` is given,
+/// missing headers are inserted and pushed to it, only the findings which are left
+/// are reported.
+pub fn scan(files: &[Change], branch: Option<&str>) -> anyhow::Result<Report> {
+    check_cancelled()?;
+    let mut detector = Detector::new(&std::env::current_dir()?)?;
+    let mut report = detector.scan_changes(files)?;
+    detector.suggest(&mut report.findings);
+    let branch = match branch {
+        Some(x) => x,
+        None => return Ok(report),
+    };
+    let fixed = detector.fix(&report.findings);
+    if fixed.is_empty() {
+        return Ok(report);
+    }
+    tracing::info!("push {} fixed file(s) to {}", fixed.len(), branch);
+    let mut args = vec!["add", "--"];
+    args.extend(fixed.iter().map(|x| x.as_str()));
+    run_command(&args)?;
+    run_command(&["commit", "-m", FIX_COMMIT_MESSAGE])?;
+    run_command(&["push", "origin", &format!("HEAD:refs/heads/{}", branch)])?;
+    report.mark_fixed(fixed);
+    Ok(report)
+}
+
+/// Markdown summary of a report, e.g. for a check run.
+pub fn render_summary(report: &Report) -> String {
+    let mut body = format!(
+        "{} file(s) scanned, {} violation(s)",
+        report.scanned.len(),
+        report.findings.len()
+    );
+    if !report.fixed.is_empty() {
+        body.push_str(&format!(", {} file(s) fixed", report.fixed.len()));
+    }
+    body.push_str("\n\n| Language | Scanned | Violations |\n| --- | ---: | ---: |\n");
+    for (language, counts) in reporter::summarize(report).iter() {
+        body.push_str(&format!(
+            "| {} | {} | {} |\n",
+            language, counts.scanned, counts.violations
+        ));
+    }
+    if !report.findings.is_empty() {
+        body.push('\n');
+    }
+    for finding in report.findings.iter().take(SUMMARY_FINDINGS_LIMIT) {
+        body.push_str(&format!("- `{}`: {}\n", finding.path, finding.reason));
+    }
+    if report.findings.len() > SUMMARY_FINDINGS_LIMIT {
+        body.push_str(&format!(
+            "- ... and {} more\n",
+            report.findings.len() - SUMMARY_FINDINGS_LIMIT
+        ));
+    }
+    body
+}
+
+// hidden first line of an inline comment, a link reference definition isn't rendered
+const FILE_COMMENT_MARKER: &str = "[//]: # (copyright:";
+
+/// Markdown body of the inline comment on an offending file. A suggestion can be
+/// applied only when the comment is `anchored` at the line it replaces.
+pub fn render_file_comment(finding: &Finding, anchored: bool) -> String {
+    let mut body = format!("{}{})\n\n", FILE_COMMENT_MARKER, finding.path);
+    body.push_str(&format!("{}.", finding.reason));
+    if let Some(suggestion) = &finding.suggestion {
+        if anchored {
+            body.push_str(&format!("\n\n```suggestion\n{}\n```", suggestion.text));
+        } else {
+            body.push_str(&format!(
+                " Replace line {} with:\n\n```\n{}\n```",
+                suggestion.line, suggestion.text
+            ));
+        }
+    }
+    body.push('\n');
+    body
+}
+
+/// The path an inline comment of the bot is about, None for other comments.
+pub fn file_comment_path(body: &str) -> Option<&str> {
+    body.lines()
+        .next()?
+        .strip_prefix(FILE_COMMENT_MARKER)?
+        .strip_suffix(')')
+}
 
-pub fn scan() -> anyhow::Result<bool> {
-    let detector = detector::Detector::new();
-    detector.scan()
+/// An inline comment of the bot on a file, `id` is what the SCM resolves it by.
+pub struct FileComment<T> {
+    pub id: T,
+    pub path: String,
+    /// neither resolved nor closed
+    pub open: bool,
+    /// the line it's anchored at is gone from the diff
+    pub outdated: bool,
+}
+
+/// A comment to post on an offending file.
+pub struct NewFileComment<'a> {
+    pub finding: &'a Finding,
+    /// line the comment is anchored at, None when it's on the whole file
+    pub line: Option<usize>,
+}
+
+/// Reconcile the inline comments of earlier runs with the report, the open comments to
+/// resolve and the comments to post are handed back. A file which still has a comment
+/// isn't commented again, comments on fixed files and outdated comments are resolved.
+/// Only files added by the pull-request are commented at a line, the lines of other
+/// files may not be part of the diff.
+pub fn reconcile_file_comments<'a, T>(
+    comments: Vec<FileComment<T>>,
+    files: &[Change],
+    report: &'a Report,
+) -> (Vec<FileComment<T>>, Vec<NewFileComment<'a>>) {
+    let offending: HashSet<&str> = report.findings.iter().map(|x| x.path.as_str()).collect();
+    let mut commented: HashSet<String> = HashSet::new();
+    let mut stale: Vec<FileComment<T>> = vec![];
+    for comment in comments {
+        if offending.contains(comment.path.as_str()) && !comment.outdated {
+            commented.insert(comment.path);
+        } else if comment.open {
+            stale.push(comment);
+        }
+    }
+    let added: HashSet<&str> = files
+        .iter()
+        .filter(|x| x.kind == ChangeKind::Added)
+        .map(|x| x.path.as_str())
+        .collect();
+    let new = report
+        .findings
+        .iter()
+        .filter(|x| !commented.contains(&x.path))
+        .map(|x| NewFileComment {
+            finding: x,
+            line: added
+                .contains(x.path.as_str())
+                .then(|| x.suggestion.as_ref().map_or(x.lines.0, |s| s.line)),
+        })
+        .collect();
+    (stale, new)
+}
+
+// hidden first line of the comment which reports the scans of a pull-request
+const REPORT_COMMENT_MARKER: &str = "[//]: # (copyright-report)";
+// earlier versions posted a comment titled like "Copyright is missing - reported by CICD"
+const LEGACY_COMMENT_SUFFIX: &str = "reported by CICD";
+const HISTORY_TITLE: &str = "#### History";
+// runs listed in the history of the comment
+const HISTORY_LIMIT: usize = 10;
+
+/// Whether the comment is the one which reports the scans, it's edited on every run.
+pub fn is_report_comment(body: &str) -> bool {
+    let title = body.lines().next().unwrap_or_default();
+    title == REPORT_COMMENT_MARKER || title.ends_with(LEGACY_COMMENT_SUFFIX)
+}
+
+/// Markdown body of the comment which reports the scans of a pull-request: the status,
+/// the offending files and the history of runs, which is carried over from `previous`.
+pub fn render_report_comment(report: &Report, commit: &str, previous: Option<&str>) -> String {
+    let (state, description) = describe(report);
+    let status = match state {
+        BuildState::Successful => "passed",
+        _ => "failed",
+    };
+    let mut body = format!("{}\n### Copyright\n\n", REPORT_COMMENT_MARKER);
+    body.push_str(&format!("**Status:** {} - {}\n", status, description));
+    if !report.findings.is_empty() {
+        body.push_str("\n| File | Language | Problem |\n| --- | --- | --- |\n");
+        for finding in report.findings.iter() {
+            body.push_str(&format!(
+                "| `{}` | {} | {} |\n",
+                finding.path, finding.language, finding.reason
+            ));
+        }
+    }
+    if !report.fixed.is_empty() {
+        body.push_str("\nThe header is added to:\n");
+        for path in report.fixed.iter() {
+            body.push_str(&format!("- `{}`\n", path));
+        }
+    }
+    body.push_str(&format!("\n{}\n", HISTORY_TITLE));
+    let short: String = commit.chars().take(8).collect();
+    let now = chrono::Utc::now().format("%Y-%m-%d %H:%M UTC");
+    body.push_str(&format!("- {} `{}`: {}\n", now, short, description));
+    let history = previous
+        .and_then(|x| x.split_once(HISTORY_TITLE))
+        .map(|(_, x)| x)
+        .unwrap_or_default();
+    for line in history
+        .lines()
+        .filter(|x| x.starts_with("- "))
+        .take(HISTORY_LIMIT - 1)
+    {
+        body.push_str(line);
+        body.push('\n');
+    }
+    body
+}
+
+/// Local HTTP fakes of the SCM APIs for the tests of the handlers.
+#[cfg(test)]
+mod fake {
+    use std::net::TcpListener;
+
+    /// Serve `router` on a free port and answer its URL. The handlers use blocking
+    /// clients which can't run inside a runtime, so the fake gets a thread of its own.
+    pub fn serve(router: axum::Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                axum::Server::from_tcp(listener)
+                    .unwrap()
+                    .serve(router.into_make_service())
+                    .await
+                    .unwrap();
+            })
+        });
+        url
+    }
+
+    /// Credentials which the handlers read from the environment.
+    pub fn credentials() {
+        std::env::set_var("GIT_USERNAME", "fake");
+        std::env::set_var("GIT_PASSWORD", "secret");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use detector::Reason;
+
+    fn finding(path: &str) -> Finding {
+        Finding {
+            path: path.to_string(),
+            language: "csharp".to_string(),
+            reason: Reason::Missing,
+            lines: (1, 1),
+            suggestion: Some(fixer::Suggestion {
+                line: 3,
+                text: "// Copyright\n".to_string(),
+            }),
+        }
+    }
+
+    fn comment(id: u32, path: &str, open: bool, outdated: bool) -> FileComment<u32> {
+        FileComment {
+            id,
+            path: path.to_string(),
+            open,
+            outdated,
+        }
+    }
+
+    #[test]
+    fn file_comments_are_reconciled() {
+        let report = Report {
+            findings: vec![finding("added.cs"), finding("kept.cs"), finding("moved.cs")],
+            ..Default::default()
+        };
+        let files = [
+            Change::new("added.cs", ChangeKind::Added),
+            Change::new("kept.cs", ChangeKind::Modified),
+            Change::new("moved.cs", ChangeKind::Modified),
+        ];
+        let comments = vec![
+            comment(1, "kept.cs", true, false),
+            // the anchor is gone, it's replaced by a new comment
+            comment(2, "moved.cs", true, true),
+            comment(3, "fixed.cs", true, false),
+            comment(4, "fixed.cs", false, false),
+        ];
+        let (stale, new) = reconcile_file_comments(comments, &files, &report);
+        assert_eq!(stale.iter().map(|x| x.id).collect::<Vec<u32>>(), vec![2, 3]);
+        let new: Vec<(&str, Option<usize>)> = new
+            .iter()
+            .map(|x| (x.finding.path.as_str(), x.line))
+            .collect();
+        // only an added file is commented at the line of the suggestion
+        assert_eq!(new, vec![("added.cs", Some(3)), ("moved.cs", None)]);
+    }
+
+    #[test]
+    fn file_comment_path_is_read_back() {
+        let body = render_file_comment(&finding("src/a.cs"), true);
+        assert_eq!(file_comment_path(&body), Some("src/a.cs"));
+        assert_eq!(file_comment_path("Copyright is missing"), None);
+    }
 }
@@ -13,6 +13,52 @@ pub struct BitbucketPagePayload {
     pub start: u32,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketPullRequestPayload {
+    pub id: i64,
+    pub version: i32,
+    pub from_ref: BitbucketRef,
+    pub to_ref: BitbucketRef,
+    #[serde(default)]
+    pub links: BitbucketLinks,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketLinks {
+    #[serde(rename = "self", default)]
+    pub self_field: Vec<BitbucketLink>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketLink {
+    pub href: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketRef {
+    pub id: String,
+    pub display_id: String,
+    pub latest_commit: String,
+    pub repository: BitbucketRepository,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketRepository {
+    pub slug: String,
+    pub project: BitbucketProject,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketProject {
+    pub key: String,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BitbucketChangesPayload {
@@ -128,37 +174,15 @@ pub struct Comment {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CommentAnchor {
-    pub line: i64,
-    pub line_type: String,
-    pub file_type: String,
+    // a comment on the whole file has no line
+    pub line: Option<i64>,
+    pub line_type: Option<String>,
+    pub file_type: Option<String>,
     pub path: String,
-    pub src_path: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Added {
-    pub commits: Vec<Commit>,
-    pub total: i64,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Commit {
-    pub id: String,
-    pub display_id: String,
-    pub author: BitbucketUser,
-    pub author_timestamp: i64,
-    pub committer: Committer,
-    pub committer_timestamp: i64,
-    pub message: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Committer {
-    pub name: String,
-    pub email_address: String,
+    pub src_path: Option<String>,
+    /// the anchor is gone from the diff of the pull-request
+    #[serde(default)]
+    pub orphaned: bool,
 }
 
 // #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
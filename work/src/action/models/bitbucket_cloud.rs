@@ -0,0 +1,104 @@
+use serde::Serialize;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudPagePayload {
+    pub values: Value,
+    // URL of the next page, it's missing on the last one
+    pub next: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudPullRequestPayload {
+    pub id: i64,
+    pub source: CloudRef,
+    pub destination: CloudRef,
+    #[serde(default)]
+    pub links: CloudLinks,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudLinks {
+    pub html: Option<CloudLink>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudLink {
+    pub href: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudRef {
+    pub branch: CloudBranch,
+    pub commit: CloudCommit,
+    pub repository: CloudRepository,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudBranch {
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudCommit {
+    pub hash: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudRepository {
+    // `{workspace}/{slug}`
+    pub full_name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudDiffstatPayload {
+    pub status: String,
+    pub old: Option<CloudFile>,
+    pub new: Option<CloudFile>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudFile {
+    pub path: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudComment {
+    pub id: i64,
+    pub content: CloudContent,
+    pub inline: Option<CloudInline>,
+    #[serde(default)]
+    pub deleted: bool,
+    // set once the thread is resolved
+    pub resolution: Option<Value>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudContent {
+    pub raw: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CloudInline {
+    pub path: String,
+    // a comment on the whole file has no line
+    pub to: Option<i64>,
+    pub from: Option<i64>,
+    /// the line is gone from the diff of the pull-request
+    #[serde(default)]
+    pub outdated: bool,
+}
@@ -1,4 +1,5 @@
 use serde_json::Value;
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::Serialize;
 use serde::Deserialize;
 
@@ -10,43 +11,195 @@ pub struct GithubPayload {
     pub errors: Option<Vec<GithubErrorPayload>>,
 }
 
+/// A GraphQL document of the GitHub API, it's sent together with its typed variables
+/// and the `data` of the response is decoded into `Data`. Values never go into the
+/// document itself.
+pub trait GithubQuery {
+    const DOCUMENT: &'static str;
+    type Variables: Serialize;
+    type Data: DeserializeOwned;
+}
+
 // Add comment
-pub const GITHUB_ADD_COMMENT: &'static str = r#"mutation {
-  addComment(input: {subjectId: "{{id}}", body: "{{body}}", clientMutationId: "copyright-add-comment"}) {
+pub struct AddComment;
+
+impl GithubQuery for AddComment {
+    const DOCUMENT: &'static str = r#"mutation($id: ID!, $body: String!) {
+  addComment(input: {subjectId: $id, body: $body, clientMutationId: "copyright-add-comment"}) {
     clientMutationId
   }
 }
 "#;
+    type Variables = CommentVariables;
+    type Data = IgnoredAny;
+}
+
+// Update comment
+pub struct UpdateComment;
 
-// Delete comment
-pub const GITHUB_DELETE_COMMENT: &'static str = r#"mutation {
-  deleteIssueComment(input: {id: "{{id}}", clientMutationId: "copyright-delete-comment"}) {
+impl GithubQuery for UpdateComment {
+    const DOCUMENT: &'static str = r#"mutation($id: ID!, $body: String!) {
+  updateIssueComment(input: {id: $id, body: $body, clientMutationId: "copyright-update-comment"}) {
     clientMutationId
   }
 }
 "#;
+    type Variables = CommentVariables;
+    type Data = IgnoredAny;
+}
+
+/// `id` is the pull-request for a new comment, the comment itself for an update.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentVariables {
+    pub id: String,
+    pub body: String,
+}
+
+// Resolve review thread
+pub struct ResolveThread;
+
+impl GithubQuery for ResolveThread {
+    const DOCUMENT: &'static str = r#"mutation($id: ID!) {
+  resolveReviewThread(input: {threadId: $id, clientMutationId: "copyright-resolve-thread"}) {
+    clientMutationId
+  }
+}
+"#;
+    type Variables = NodeVariables;
+    type Data = IgnoredAny;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeVariables {
+    pub id: String,
+}
+
+// Query review threads in Pull-Request
+pub struct ThreadsQuery;
+
+impl GithubQuery for ThreadsQuery {
+    const DOCUMENT: &'static str = r#"query($owner: String!, $repository: String!, $number: Int!, $after: String) {
+  repository(owner: $owner, name: $repository) {
+    pullRequest(number: $number) {
+      reviewThreads(first: 50, after: $after) {
+        edges {
+          node {
+            id
+            isResolved
+            isOutdated
+            comments(first: 1) {
+              nodes {
+                body
+              }
+            }
+          }
+        }
+        pageInfo {
+          endCursor
+          hasNextPage
+        }
+      }
+    }
+  }
+}
+"#;
+    type Variables = PullRequestVariables;
+    type Data = GithubThreadsPayload;
+}
+
+/// A pull-request and the cursor of the connection which is paged.
+#[derive(Debug, Clone, Serialize)]
+pub struct PullRequestVariables {
+    pub owner: String,
+    pub repository: String,
+    pub number: u32,
+    pub after: Option<String>,
+}
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct GithubDeleteCommentPayload {
-    pub delete_issue_comment: GithubDeleteIssueComment,
+pub struct GithubThreadsPayload {
+    pub repository: GithubThreadsRepository,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct GithubDeleteIssueComment {
-    pub client_mutation_id: Value,
+pub struct GithubThreadsRepository {
+    pub pull_request: GithubThreadsPullRequest,
 }
 
-// Query Files in Pull-Request
-pub const GITHUB_QUERY: &'static str = r#"query {
-  repository(owner: "{{project}}", name: "{{repository}}") {
-    pullRequest(number: {{number}}) {
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubThreadsPullRequest {
+    pub review_threads: GithubThreadsInPull,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubThreadsInPull {
+    pub edges: Vec<GithubThreadsEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubThreadsEdge {
+    pub node: GithubThreadNode,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubThreadNode {
+    pub id: String,
+    pub is_resolved: bool,
+    pub is_outdated: bool,
+    pub comments: GithubThreadComments,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubThreadComments {
+    pub nodes: Vec<GithubThreadComment>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubThreadComment {
+    pub body: String,
+}
+
+// Query Pull-Request
+pub struct PullRequestQuery;
+
+impl GithubQuery for PullRequestQuery {
+    const DOCUMENT: &'static str = r#"query($owner: String!, $repository: String!, $number: Int!) {
+  repository(owner: $owner, name: $repository) {
+    pullRequest(number: $number) {
       id
-      files(first: 50{% if file_after != "" %},  after: "{{file_after}}" {% endif %}) {
+      headRefName
+      headRefOid
+      isCrossRepository
+      changedFiles
+    }
+  }
+}
+"#;
+    type Variables = PullRequestVariables;
+    type Data = GithubPullRequestPayload;
+}
+
+// Query Files in Pull-Request
+pub struct FilesQuery;
+
+impl GithubQuery for FilesQuery {
+    const DOCUMENT: &'static str = r#"query($owner: String!, $repository: String!, $number: Int!, $after: String) {
+  repository(owner: $owner, name: $repository) {
+    pullRequest(number: $number) {
+      files(first: 100, after: $after) {
         edges {
           node {
             path
+            changeType
           }
         }
         pageInfo {
@@ -54,7 +207,22 @@ pub const GITHUB_QUERY: &'static str = r#"query {
           hasNextPage
         }
       }
-      comments(first: 50{% if comment_after != "" %}, after: "{{comment_after}}" {% endif %}) {
+    }
+  }
+}
+"#;
+    type Variables = PullRequestVariables;
+    type Data = GithubPullRequestPayload;
+}
+
+// Query Comments in Pull-Request
+pub struct CommentsQuery;
+
+impl GithubQuery for CommentsQuery {
+    const DOCUMENT: &'static str = r#"query($owner: String!, $repository: String!, $number: Int!, $after: String) {
+  repository(owner: $owner, name: $repository) {
+    pullRequest(number: $number) {
+      comments(first: 100, after: $after) {
         edges {
           node {
             id
@@ -70,6 +238,15 @@ pub const GITHUB_QUERY: &'static str = r#"query {
   }
 }
 "#;
+    type Variables = PullRequestVariables;
+    type Data = GithubPullRequestPayload;
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GithubCheckRunPayload {
+    pub id: u64,
+}
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -83,10 +260,15 @@ pub struct GithubRepository {
     pub pull_request: GithubPullRequest,
 }
 
+/// Every query selects a part of the pull-request, the rest is left default.
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct GithubPullRequest {
     pub id: String,
+    pub head_ref_name: String,
+    pub head_ref_oid: String,
+    pub is_cross_repository: bool,
+    pub changed_files: u32,
     pub files: GithubFilesInPull,
     pub comments: GithubCommentsInPull,
 }
@@ -108,6 +290,7 @@ pub struct GithubFilesEdge {
 #[serde(rename_all = "camelCase")]
 pub struct GithubFilesNode {
     pub path: String,
+    pub change_type: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -1,16 +1,23 @@
 mod github;
 mod bitbucket;
+mod bitbucket_cloud;
 
-pub use github::GITHUB_QUERY;
-pub use github::GITHUB_DELETE_COMMENT;
-pub use github::GITHUB_ADD_COMMENT;
-pub use github::GithubPullRequestPayload;
 pub use github::GithubPayload;
-pub use github::GithubErrorPayload;
+pub use github::GithubCheckRunPayload;
+pub use github::GithubQuery;
+pub use github::{AddComment, ResolveThread, UpdateComment};
+pub use github::{CommentsQuery, FilesQuery, PullRequestQuery, ThreadsQuery};
+pub use github::{CommentVariables, NodeVariables, PageInfo, PullRequestVariables};
 
 pub use bitbucket::BitbucketChangesPayload;
 pub use bitbucket::BitbucketActivitiesPayload;
 pub use bitbucket::BitbucketPagePayload;
+pub use bitbucket::BitbucketPullRequestPayload;
+pub use bitbucket::BitbucketRef;
+pub use bitbucket::Comment as BitbucketComment;
+pub use bitbucket::CommentAnchor;
 
-pub const BAD_COMMENT: &'static str = "Copyright is missing - reported by CICD";
-pub const GOOD_COMMENT: &'static str = "Copyright is OK - reported by CICD";
\ No newline at end of file
+pub use bitbucket_cloud::CloudPagePayload;
+pub use bitbucket_cloud::CloudPullRequestPayload;
+pub use bitbucket_cloud::CloudDiffstatPayload;
+pub use bitbucket_cloud::CloudComment;
@@ -0,0 +1,214 @@
+use crate::action::detector::{Reason, Report};
+use crate::action::sarif;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// Version of the JSON report, it's increased whenever the schema changes incompatibly.
+const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Output formats of a scan report.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    /// one line per finding and a summary
+    #[default]
+    Text,
+    /// machine-readable report with a stable schema
+    Json,
+    /// JUnit XML, one testcase per scanned file
+    Junit,
+    /// SARIF 2.1.0 log
+    Sarif,
+}
+
+impl Format {
+    pub fn reporter(&self) -> Box<dyn Reporter> {
+        match self {
+            Format::Text => Box::new(TextReporter),
+            Format::Json => Box::new(JsonReporter),
+            Format::Junit => Box::new(JunitReporter),
+            Format::Sarif => Box::new(SarifReporter),
+        }
+    }
+}
+
+pub trait Reporter {
+    /// Render the whole report as the content of a file.
+    fn render(&self, report: &Report) -> anyhow::Result<String>;
+}
+
+/// Counts of one language in the summary.
+#[derive(Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Counts {
+    pub scanned: usize,
+    pub violations: usize,
+}
+
+/// Counts per language, sorted by the language name.
+pub fn summarize(report: &Report) -> BTreeMap<&str, Counts> {
+    let mut summary: BTreeMap<&str, Counts> = BTreeMap::new();
+    for file in report.scanned.iter() {
+        summary.entry(&file.language).or_default().scanned += 1;
+    }
+    for finding in report.findings.iter() {
+        summary.entry(&finding.language).or_default().violations += 1;
+    }
+    summary
+}
+
+/// Name of the reason as it's written into machine-readable reports, e.g. `missing-year`.
+fn reason_name(reason: Reason) -> String {
+    match serde_json::to_value(reason) {
+        Ok(serde_json::Value::String(x)) => x,
+        _ => unreachable!("reason is serialized as a string"),
+    }
+}
+
+pub struct TextReporter;
+
+impl Reporter for TextReporter {
+    fn render(&self, report: &Report) -> anyhow::Result<String> {
+        let mut content = String::new();
+        for path in report.fixed.iter() {
+            content.push_str(&format!("{}: copyright header is added\n", path));
+        }
+        for finding in report.findings.iter() {
+            content.push_str(&format!(
+                "{}:{}: {}\n",
+                finding.path, finding.lines.0, finding.reason
+            ));
+        }
+        if report.findings.is_empty() {
+            content.push_str("copyright headers are OK\n");
+        } else {
+            content.push_str(&format!(
+                "{} file(s) violate the copyright policy\n",
+                report.findings.len()
+            ));
+        }
+        for file in report.skipped.iter() {
+            content.push_str(&format!("{}: skipped, {}\n", file.path, file.reason));
+        }
+        for (language, counts) in summarize(report).iter() {
+            content.push_str(&format!(
+                "  {}: {} scanned, {} violation(s)\n",
+                language, counts.scanned, counts.violations
+            ));
+        }
+        Ok(content)
+    }
+}
+
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn render(&self, report: &Report) -> anyhow::Result<String> {
+        let findings: Vec<serde_json::Value> = report
+            .findings
+            .iter()
+            .map(|x| {
+                json!({
+                    "path": x.path,
+                    "language": x.language,
+                    "reason": reason_name(x.reason),
+                    "message": x.reason.to_string(),
+                    "startLine": x.lines.0,
+                    "endLine": x.lines.1,
+                })
+            })
+            .collect();
+        let log = json!({
+            "version": JSON_SCHEMA_VERSION,
+            "tool": {
+                "name": "copyright",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "summary": {
+                "scanned": report.scanned.len(),
+                "violations": report.findings.len(),
+                "fixed": report.fixed.len(),
+                "skipped": report.skipped.len(),
+                "languages": summarize(report),
+            },
+            "findings": findings,
+            "fixed": report.fixed,
+            "skipped": report.skipped,
+        });
+        Ok(serde_json::to_string_pretty(&log)? + "\n")
+    }
+}
+
+pub struct JunitReporter;
+
+impl Reporter for JunitReporter {
+    fn render(&self, report: &Report) -> anyhow::Result<String> {
+        let summary = summarize(report);
+        let mut content = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        content.push_str(&format!(
+            "<testsuites name=\"copyright\" tests=\"{}\" failures=\"{}\">\n",
+            report.scanned.len(),
+            report.findings.len()
+        ));
+        for (language, counts) in summary.iter() {
+            content.push_str(&format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+                escape(language),
+                counts.scanned,
+                counts.violations
+            ));
+            for file in report.scanned.iter().filter(|x| &x.language == language) {
+                let head = format!(
+                    "    <testcase classname=\"copyright.{}\" name=\"{}\"",
+                    escape(language),
+                    escape(&file.path)
+                );
+                match report.findings.iter().find(|x| x.path == file.path) {
+                    Some(finding) => {
+                        content.push_str(&head);
+                        content.push_str(">\n");
+                        content.push_str(&format!(
+                            "      <failure type=\"{}\" message=\"{}\">{}:{}</failure>\n",
+                            reason_name(finding.reason),
+                            escape(&finding.reason.to_string()),
+                            escape(&finding.path),
+                            finding.lines.0
+                        ));
+                        content.push_str("    </testcase>\n");
+                    }
+                    None => {
+                        content.push_str(&head);
+                        content.push_str("/>\n");
+                    }
+                }
+            }
+            content.push_str("  </testsuite>\n");
+        }
+        content.push_str("</testsuites>\n");
+        Ok(content)
+    }
+}
+
+pub struct SarifReporter;
+
+impl Reporter for SarifReporter {
+    fn render(&self, report: &Report) -> anyhow::Result<String> {
+        let log = sarif::render(&report.findings);
+        Ok(serde_json::to_string_pretty(&log)? + "\n")
+    }
+}
+
+/// Escape text for XML attributes and content.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,61 @@
+use crate::action::detector::{Finding, Reason};
+use serde_json::{json, Value};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Every check of the detector, a SARIF rule is reported for each.
+const RULES: &[(Reason, &str, &str)] = &[
+    (Reason::Missing, "missing-header", "MissingHeader"),
+    (Reason::Malformed, "malformed-header", "MalformedHeader"),
+    (Reason::MissingYear, "missing-year", "MissingYear"),
+    (Reason::OutdatedYear, "stale-year", "StaleYear"),
+    (Reason::DecodeError, "undecodable-file", "UndecodableFile"),
+];
+
+/// SARIF 2.1.0 log with one result per finding.
+pub fn render(findings: &[Finding]) -> Value {
+    let rules: Vec<Value> = RULES
+        .iter()
+        .map(|(reason, id, name)| {
+            json!({
+                "id": id,
+                "name": name,
+                "shortDescription": { "text": reason.to_string() },
+                "defaultConfiguration": { "level": "error" },
+            })
+        })
+        .collect();
+    let results: Vec<Value> = findings
+        .iter()
+        .map(|x| {
+            let index = RULES.iter().position(|r| r.0 == x.reason).unwrap();
+            json!({
+                "ruleId": RULES[index].1,
+                "ruleIndex": index,
+                "level": "error",
+                "message": { "text": format!("{} ({})", x.reason, x.language) },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": x.path, "uriBaseId": "%SRCROOT%" },
+                        "region": { "startLine": x.lines.0, "endLine": x.lines.1 },
+                    }
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "copyright",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "semanticVersion": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    })
+}
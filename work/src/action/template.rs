@@ -0,0 +1,400 @@
+use chrono::Datelike;
+use regex::Regex;
+
+/// How a header is decorated as a comment in a particular language.
+pub enum CommentStyle {
+    /// `/* ... */` with ` * ` in front of the inner lines, `//` lines are accepted as well
+    Block,
+    /// every line starts with the given marker, e.g. `//` or `#`
+    Line(&'static str),
+    /// `<!-- ... -->` with the inner lines indented
+    Xml,
+    /// batch files, `REM` is written but `::` is accepted as well
+    Rem,
+}
+
+impl CommentStyle {
+    /// Whether the line belongs to a comment written in this style.
+    pub fn is_comment(&self, line: &str) -> bool {
+        let line = line.trim_start();
+        match self {
+            CommentStyle::Block => {
+                line.starts_with("/*") || line.starts_with('*') || line.starts_with("//")
+            }
+            CommentStyle::Line(marker) => line.starts_with(marker),
+            CommentStyle::Xml => !line.starts_with('<') || line.starts_with("<!--"),
+            CommentStyle::Rem => {
+                let lower = line.to_lowercase();
+                lower.starts_with("rem") || lower.starts_with("@rem") || line.starts_with("::")
+            }
+        }
+    }
+
+    /// Whether the line terminates a comment which spans several lines.
+    pub fn is_closed(&self, line: &str) -> bool {
+        match self {
+            CommentStyle::Block => line.contains("*/"),
+            CommentStyle::Xml => line.contains("-->"),
+            _ => false,
+        }
+    }
+
+    /// Styles a header may be written in for languages using this style.
+    fn accepted(&self) -> Vec<&CommentStyle> {
+        match self {
+            CommentStyle::Block => vec![self, &CommentStyle::Line("//")],
+            _ => vec![self],
+        }
+    }
+
+    fn open(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            CommentStyle::Block => Some(("/*", r"/\*+")),
+            CommentStyle::Xml => Some(("<!--", "<!--")),
+            _ => None,
+        }
+    }
+
+    fn close(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            CommentStyle::Block => Some((" */", r"\*+/")),
+            CommentStyle::Xml => Some(("-->", "-->")),
+            _ => None,
+        }
+    }
+
+    /// Text written in front of every inner line and the regex accepting it.
+    fn prefix(&self) -> (String, String) {
+        match self {
+            CommentStyle::Block => (" *".to_string(), r"\*?".to_string()),
+            CommentStyle::Line(marker) => (marker.to_string(), regex::escape(marker)),
+            CommentStyle::Xml => (" ".to_string(), "".to_string()),
+            CommentStyle::Rem => ("REM".to_string(), "(?:@?(?i:rem)|::)".to_string()),
+        }
+    }
+}
+
+/// Canonical header text without any comment decoration,
+/// it's decorated per language for both matching and generation.
+/// The text may contain the placeholders `{{year}}` and `{{holder}}`,
+/// a text without `{{year}}` accepts an optional year behind the word "Copyright".
+pub struct Template {
+    lines: Vec<String>,
+    holder: Option<String>,
+}
+
+const PLACEHOLDER: &str = r"\{\{\s*(\w+\??)\s*\}\}";
+const PLACEHOLDERS: &[&str] = &["year", "holder"];
+// internal placeholder of the optional year
+const OPTIONAL_YEAR: &str = "{{year?}}";
+
+impl Template {
+    /// Template of a built-in header, it's known to be valid.
+    pub fn new(text: &str) -> Template {
+        Template::parse(text, None).unwrap()
+    }
+
+    /// Template of a configured header, `holder` is the value of `{{holder}}`.
+    pub fn parse(text: &str, holder: Option<&str>) -> anyhow::Result<Template> {
+        let mut lines: Vec<String> = text.lines().map(|x| x.trim_end().to_string()).collect();
+        if lines.iter().all(|x| x.is_empty()) {
+            return Err(anyhow::anyhow!("template is empty"));
+        }
+        let reg = Regex::new(PLACEHOLDER).unwrap();
+        let mut has_year = false;
+        for line in lines.iter() {
+            for cap in reg.captures_iter(line) {
+                if !PLACEHOLDERS.contains(&&cap[1]) {
+                    return Err(anyhow::anyhow!("unknown placeholder '{}'", &cap[0]));
+                }
+                has_year |= &cap[1] == "year";
+            }
+        }
+        if !has_year {
+            let copyright = Regex::new(r"(?i)copyright(?:[ \t]+(?:©|\(c\)))?").unwrap();
+            if let Some(line) = lines.iter_mut().find(|x| copyright.is_match(x)) {
+                let slot = copyright.find(line).unwrap().end();
+                line.insert_str(slot, OPTIONAL_YEAR);
+            }
+        }
+        Ok(Template {
+            lines,
+            holder: holder.map(|x| x.to_string()),
+        })
+    }
+
+    /// The header as it's inserted into a file, lines end with '\n'.
+    /// `year` is written into the header, the current year is used for
+    /// `{{year}}` when it's not given.
+    pub fn render(&self, style: &CommentStyle, year: Option<i32>) -> anyhow::Result<String> {
+        let mut content = String::new();
+        if let Some((open, _)) = style.open() {
+            content.push_str(open);
+            content.push('\n');
+        }
+        let (prefix, _) = style.prefix();
+        let reg = Regex::new(PLACEHOLDER).unwrap();
+        for line in self.lines.iter() {
+            if line.is_empty() {
+                content.push_str(prefix.trim_end());
+            } else {
+                let mut missing = None;
+                let line = reg.replace_all(line, |cap: &regex::Captures| match &cap[1] {
+                    "year" => year
+                        .unwrap_or_else(|| chrono::Local::now().year())
+                        .to_string(),
+                    "year?" => year.map(|x| format!(" {}", x)).unwrap_or_default(),
+                    _ => self.holder.clone().unwrap_or_else(|| {
+                        missing = Some(cap[0].to_string());
+                        "".to_string()
+                    }),
+                });
+                if let Some(x) = missing {
+                    return Err(anyhow::anyhow!("placeholder '{}' has no value", x));
+                }
+                content.push_str(&prefix);
+                content.push(' ');
+                content.push_str(&line);
+            }
+            content.push('\n');
+        }
+        if let Some((close, _)) = style.close() {
+            content.push_str(close);
+            content.push('\n');
+        }
+        Ok(content)
+    }
+
+    /// Regex matching the header in the given style, it's tolerant to
+    /// indentation, the amount of whitespace between words and line endings.
+    pub fn pattern(&self, style: &CommentStyle) -> Regex {
+        let alternatives = style
+            .accepted()
+            .into_iter()
+            .map(|x| self.style_pattern(x))
+            .collect::<Vec<String>>();
+        Regex::new(&format!("(?m){}", alternatives.join("|"))).unwrap()
+    }
+
+    fn style_pattern(&self, style: &CommentStyle) -> String {
+        const EOL: &str = r"[ \t]*(?:\r?\n|\z)";
+        let mut reg_str = String::new();
+        if let Some((_, open)) = style.open() {
+            reg_str.push_str(&format!(r"^[ \t]*{}{}", open, EOL));
+        }
+        let (_, prefix) = style.prefix();
+        let reg = Regex::new(PLACEHOLDER).unwrap();
+        for line in self.lines.iter() {
+            let mut words = String::new();
+            let mut last = 0;
+            for cap in reg.captures_iter(line) {
+                let found = cap.get(0).unwrap();
+                words.push_str(&literal_pattern(&line[last..found.start()]));
+                words.push_str(&match &cap[1] {
+                    "year" => YEAR_PATTERN.to_string(),
+                    "year?" => format!(r"(?:[ \t]+{},?)?", YEAR_PATTERN),
+                    _ => match &self.holder {
+                        Some(x) => literal_pattern(x),
+                        None => ".+?".to_string(),
+                    },
+                });
+                last = found.end();
+            }
+            words.push_str(&literal_pattern(&line[last..]));
+            reg_str.push_str(&format!(r"^[ \t]*{}[ \t]*{}{}", prefix, words, EOL));
+        }
+        if let Some((_, close)) = style.close() {
+            reg_str.push_str(&format!(r"^[ \t]*{}", close));
+        }
+        reg_str
+    }
+}
+
+/// a single year or a range such as `2015-2022` or `2015-present`
+const YEAR_PATTERN: &str = r"\d{4}(?:[ \t]*[-–][ \t]*(?:\d{4}|(?i:present)))?";
+
+/// First and last year mentioned in a header, e.g. `(2015, 2022)` for `2015-2022`.
+/// A range ending with `present` ends with the year `present` stands for.
+pub fn find_years(header: &str, present: i32) -> Option<(i32, i32)> {
+    let reg = Regex::new(r"\b(\d{4})(?:[ \t]*[-–][ \t]*(\d{4}|(?i:present)))?\b").unwrap();
+    reg.captures_iter(header)
+        .map(|cap| {
+            let start: i32 = cap[1].parse().unwrap();
+            let end: i32 = cap
+                .get(2)
+                .map_or(start, |x| x.as_str().parse().unwrap_or(present));
+            (start, end)
+        })
+        .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+}
+
+/// Regex of a literal text where any run of whitespace matches any run of blanks.
+fn literal_pattern(text: &str) -> String {
+    let mut pattern = String::new();
+    let mut blank = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !blank {
+                pattern.push_str(r"[ \t]+");
+            }
+            blank = true;
+        } else {
+            pattern.push_str(&regex::escape(&c.to_string()));
+            blank = false;
+        }
+    }
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Copyright {{year}} {{holder}}\n\nAll rights reserved.";
+
+    fn template() -> Template {
+        Template::parse(TEXT, Some("Example Corp.")).unwrap()
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        assert_eq!(
+            template()
+                .render(&CommentStyle::Line("#"), Some(2020))
+                .unwrap(),
+            "# Copyright 2020 Example Corp.\n#\n# All rights reserved.\n"
+        );
+        let year = chrono::Local::now().year();
+        assert_eq!(
+            template().render(&CommentStyle::Line("#"), None).unwrap(),
+            format!(
+                "# Copyright {} Example Corp.\n#\n# All rights reserved.\n",
+                year
+            )
+        );
+    }
+
+    #[test]
+    fn headers_are_rendered_in_every_style() {
+        let template = Template::parse("Copyright {{ year }} Example Corp.", None).unwrap();
+        let cases = [
+            (
+                CommentStyle::Block,
+                "/*\n * Copyright 2020 Example Corp.\n */\n",
+            ),
+            (
+                CommentStyle::Line("//"),
+                "// Copyright 2020 Example Corp.\n",
+            ),
+            (
+                CommentStyle::Line("--"),
+                "-- Copyright 2020 Example Corp.\n",
+            ),
+            (
+                CommentStyle::Xml,
+                "<!--\n  Copyright 2020 Example Corp.\n-->\n",
+            ),
+            (CommentStyle::Rem, "REM Copyright 2020 Example Corp.\n"),
+        ];
+        for (style, expected) in cases {
+            let header = template.render(&style, Some(2020)).unwrap();
+            assert_eq!(header, expected);
+            assert!(template.pattern(&style).is_match(&header), "{}", header);
+        }
+    }
+
+    #[test]
+    fn headers_are_matched_loosely() {
+        let block = template().pattern(&CommentStyle::Block);
+        let matched = [
+            "/**\r\n *  Copyright 2015-2022  Example Corp.\r\n *\r\n * All rights reserved.\r\n */",
+            // `//` lines are accepted for block comments
+            "// Copyright 2015 - 2022 Example Corp.\n//\n// All rights reserved.\n",
+        ];
+        for header in matched {
+            assert!(block.is_match(header), "{}", header);
+        }
+        let unmatched = [
+            "/*\n * Copyright Example Corp.\n *\n * All rights reserved.\n */\n",
+            "/*\n * Copyright 2020 Other Corp.\n *\n * All rights reserved.\n */\n",
+            "# Copyright 2020 Example Corp.\n#\n# All rights reserved.\n",
+        ];
+        for header in unmatched {
+            assert!(!block.is_match(header), "{}", header);
+        }
+        let rem = template().pattern(&CommentStyle::Rem);
+        for header in [
+            ":: Copyright 2020 Example Corp.\n::\n:: All rights reserved.\n",
+            "@rem Copyright 2020 Example Corp.\n@rem\n@rem All rights reserved.\n",
+        ] {
+            assert!(rem.is_match(header), "{}", header);
+        }
+    }
+
+    #[test]
+    fn holder_is_any_text_without_a_value() {
+        let pattern = Template::parse(TEXT, None)
+            .unwrap()
+            .pattern(&CommentStyle::Line("#"));
+        assert!(pattern.is_match("# Copyright 2020 Anyone.\n#\n# All rights reserved.\n"));
+        let err = Template::parse(TEXT, None)
+            .unwrap()
+            .render(&CommentStyle::Line("#"), Some(2020))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "placeholder '{{holder}}' has no value");
+    }
+
+    #[test]
+    fn year_is_optional_without_placeholder() {
+        let template = Template::parse("Copyright (c) Example Corp.", None).unwrap();
+        let style = CommentStyle::Line("//");
+        assert_eq!(
+            template.render(&style, Some(2020)).unwrap(),
+            "// Copyright (c) 2020 Example Corp.\n"
+        );
+        assert_eq!(
+            template.render(&style, None).unwrap(),
+            "// Copyright (c) Example Corp.\n"
+        );
+        let pattern = template.pattern(&style);
+        assert!(pattern.is_match("// Copyright (c) Example Corp.\n"));
+        assert!(pattern.is_match("// Copyright (c) 2015-2022, Example Corp.\n"));
+    }
+
+    #[test]
+    fn unknown_placeholder_is_refused() {
+        let err = Template::parse("Copyright {{year}} {{owner}}", None)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "unknown placeholder '{{owner}}'");
+        let err = Template::parse("\n  \n", None).err().unwrap();
+        assert_eq!(err.to_string(), "template is empty");
+    }
+
+    #[test]
+    fn years_are_found() {
+        let cases = [
+            ("Copyright 2020 Example", Some((2020, 2020))),
+            ("Copyright 2015-2022 Example", Some((2015, 2022))),
+            ("Copyright 2015 – 2022 Example", Some((2015, 2022))),
+            ("Copyright 2019-present Example", Some((2019, 2024))),
+            ("Copyright 2019 - Present Example", Some((2019, 2024))),
+            ("Copyright 2012, 2019-2021 Example", Some((2012, 2021))),
+            ("Copyright 2030 Example", Some((2030, 2030))),
+            ("Copyright Example", None),
+            ("Copyright 20201 Example", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(find_years(header, 2024), expected, "{}", header);
+        }
+    }
+
+    #[test]
+    fn present_is_matched_as_year() {
+        let pattern = template().pattern(&CommentStyle::Line("#"));
+        assert!(
+            pattern.is_match("# Copyright 2019-present Example Corp.\n#\n# All rights reserved.\n")
+        );
+        assert!(!pattern.is_match("# Copyright present Example Corp.\n#\n# All rights reserved.\n"));
+    }
+}
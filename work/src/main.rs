@@ -1,9 +1,16 @@
 mod action;
 
-use crate::action::{Bitbucket, Github, Handler};
-use clap::{Parser, ValueEnum};
+use crate::action::reporter::Format;
+use crate::action::{
+    git_changes, handle_termination, is_api_error, Bitbucket, BitbucketCloud,
+    BitbucketCloudEndpoints, BitbucketEndpoints, Detector, Github, GithubEndpoints, Handler,
+    Options, Report,
+};
+use clap::{Parser, Subcommand, ValueEnum};
 use rand::distributions::{Alphanumeric, DistString};
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use tracing_subscriber::filter::FilterExt;
 use tracing_subscriber::filter::{filter_fn, LevelFilter};
 use tracing_subscriber::layer::SubscriberExt;
@@ -13,6 +20,50 @@ use tracing_subscriber::Layer;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check copyright headers of a local checkout, directories or files
+    Scan(ScanArgs),
+    /// Check copyright headers of a pull-request and report the result to it
+    Pr(Box<PrArgs>),
+}
+
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// Write the report into the file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Write the report of `--format sarif` into the file, additionally to the report
+    #[arg(long)]
+    sarif: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct ScanArgs {
+    /// Root of the checkout, pipeline.yaml is loaded from here
+    #[arg(long, default_value = ".")]
+    root: PathBuf,
+    /// Insert the header into files where it is missing
+    #[arg(long)]
+    fix: bool,
+    /// Check only the files changed since the git revision, e.g. the target branch
+    #[arg(long, conflicts_with = "paths")]
+    base: Option<String>,
+    /// Files or directories to check, the whole root when omitted
+    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+#[derive(clap::Args)]
+struct PrArgs {
     #[arg(short, long)]
     project: String,
     #[arg(short, long)]
@@ -21,11 +72,80 @@ struct Args {
     id: u32,
     #[arg(short, long, value_enum)]
     scm: ScmType,
+    /// Insert missing headers and push them to the source branch
+    #[arg(long)]
+    fix: bool,
+    /// Commit the status is reported on, the head of the pull-request by default
+    #[arg(long)]
+    commit: Option<String>,
+    /// Private key to clone and push over SSH clone URLs, askpass.sh is used for HTTP ones
+    #[arg(long, env = "GIT_SSH_KEY")]
+    ssh_key: Option<PathBuf>,
+    #[command(flatten)]
+    github: GithubArgs,
+    #[command(flatten)]
+    bitbucket: BitbucketArgs,
+    #[command(flatten)]
+    bitbucket_cloud: BitbucketCloudArgs,
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+#[derive(clap::Args)]
+struct GithubArgs {
+    /// REST API of GitHub, e.g. https://ghes.example.com/api/v3 for GitHub Enterprise Server
+    #[arg(long, env = "GITHUB_API_URL", default_value = "https://api.github.com")]
+    github_api_url: String,
+    /// GraphQL API of GitHub, derived from the REST API when omitted
+    #[arg(long, env = "GITHUB_GRAPHQL_URL")]
+    github_graphql_url: Option<String>,
+    /// Clone URL of a repository with {project} and {repository}, derived when omitted
+    #[arg(long, env = "GITHUB_CLONE_URL")]
+    github_clone_url: Option<String>,
 }
+
+#[derive(clap::Args)]
+struct BitbucketArgs {
+    /// Bitbucket Server instance
+    #[arg(
+        long,
+        env = "BITBUCKET_URL",
+        default_value = "https://code-dev.do.citrite.net"
+    )]
+    bitbucket_url: String,
+    /// Context path of the REST APIs under the instance
+    #[arg(long, env = "BITBUCKET_REST_PATH", default_value = "/rest")]
+    bitbucket_rest_path: String,
+    /// Clone URL of a repository with {project} and {repository}, derived when omitted,
+    /// e.g. ssh://git@host:7999/{project}/{repository}.git
+    #[arg(long, env = "BITBUCKET_CLONE_URL")]
+    bitbucket_clone_url: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct BitbucketCloudArgs {
+    /// 2.0 API of Bitbucket Cloud
+    #[arg(
+        long,
+        env = "BITBUCKET_CLOUD_API_URL",
+        default_value = "https://api.bitbucket.org/2.0"
+    )]
+    bitbucket_cloud_api_url: String,
+    /// Clone URL of a repository with {project} as the workspace and {repository}
+    #[arg(
+        long,
+        env = "BITBUCKET_CLOUD_CLONE_URL",
+        default_value = "https://bitbucket.org/{project}/{repository}.git"
+    )]
+    bitbucket_cloud_clone_url: String,
+}
+
 #[derive(ValueEnum, Clone)]
 enum ScmType {
     Github,
+    /// Bitbucket Server and Data Center
     Bitbucket,
+    BitbucketCloud,
 }
 
 struct Workspace(PathBuf);
@@ -37,42 +157,138 @@ impl Drop for Workspace {
     }
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let target_filter = filter_fn(|meta| meta.target().starts_with("work"));
     let level_filter = LevelFilter::TRACE;
     let filter = target_filter.and(level_filter);
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_filter(filter))
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                // the webhook keeps the output in files
+                .with_ansi(std::io::stderr().is_terminal())
+                .with_filter(filter),
+        )
         .init();
     let args: Args = Args::parse();
+    let ret = match args.command {
+        Command::Scan(scan) => run_scan(&scan).and_then(|x| write_report(&scan.report, x)),
+        Command::Pr(mut pr) => {
+            // the pull-request is checked out into another directory
+            pr.report.output = pr.report.output.map(absolute_path);
+            pr.report.sarif = pr.report.sarif.map(absolute_path);
+            pr.ssh_key = pr.ssh_key.map(absolute_path);
+            handle_termination()
+                .and_then(|_| run_pull_request(&pr))
+                .and_then(|x| write_report(&pr.report, x))
+        }
+    };
+    // 0: compliant, 1: violations are found, 2: fail to run, 3: the SCM API failed
+    match ret {
+        Ok(report) if report.findings.is_empty() => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::from(1),
+        Err(err) if is_api_error(&err) => {
+            tracing::error!("{:#}", err);
+            ExitCode::from(3)
+        }
+        Err(err) => {
+            tracing::error!("{:#}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_scan(args: &ScanArgs) -> anyhow::Result<Report> {
+    let mut detector = Detector::new(&args.root)?;
+    let mut report = match &args.base {
+        Some(base) => detector.scan_changes(&git_changes(&args.root, base)?)?,
+        None if args.paths.is_empty() => detector.scan()?,
+        None => detector.scan_paths(&args.paths)?,
+    };
+    if args.fix {
+        let fixed = detector.fix(&report.findings);
+        report.mark_fixed(fixed);
+    }
+    Ok(report)
+}
+
+fn run_pull_request(args: &PrArgs) -> anyhow::Result<Report> {
     let span = tracing::span!(
         tracing::Level::TRACE,
         "copyright",
         space = format!("{}:{}:{}", &args.project, &args.repository, args.id)
     );
     let _span_handle = span.enter();
-    let handler: Option<Box<dyn Handler>> = match args.scm {
-        ScmType::Github => Some(Box::new(Github::new())),
-        ScmType::Bitbucket => Some(Box::new(Bitbucket::new(
+    let mut handler: Box<dyn Handler> = match args.scm {
+        ScmType::Github => Box::new(Github::new(GithubEndpoints::new(
+            &args.github.github_api_url,
+            args.github.github_graphql_url.clone(),
+            args.github.github_clone_url.clone(),
+        ))),
+        ScmType::Bitbucket => Box::new(Bitbucket::new(
             &args.project,
             &args.repository,
             args.id,
-        ))),
+            BitbucketEndpoints::new(
+                &args.bitbucket.bitbucket_url,
+                &args.bitbucket.bitbucket_rest_path,
+                args.bitbucket.bitbucket_clone_url.clone(),
+            ),
+        )),
+        ScmType::BitbucketCloud => Box::new(BitbucketCloud::new(
+            &args.project,
+            &args.repository,
+            args.id,
+            BitbucketCloudEndpoints::new(
+                &args.bitbucket_cloud.bitbucket_cloud_api_url,
+                &args.bitbucket_cloud.bitbucket_cloud_clone_url,
+            ),
+        )),
     };
-    match handler {
-        Some(mut h) => {
-            let workspace = create_workspace(&args.project, &args.repository, args.id);
-            std::process::Command::new("git")
-                .arg("init")
-                .arg(&workspace.0.to_str().unwrap())
-                .output()
-                .expect("failed to execute 'git init'");
-            std::env::set_current_dir(&workspace.0).expect(&workspace.0.to_str().unwrap());
-            tracing::info!("start in {}", &workspace.0.as_os_str().to_str().unwrap());
-            h.execute(&args.project, &args.repository, args.id)
-        }
-        None => Err(anyhow::anyhow!("nothing")),
+    if let Some(key) = &args.ssh_key {
+        // git runs ssh with the key for fetching and pushing
+        std::env::set_var(
+            "GIT_SSH_COMMAND",
+            format!(
+                "ssh -i '{}' -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new",
+                key.display()
+            ),
+        );
     }
+    let workspace = create_workspace(&args.project, &args.repository, args.id);
+    std::process::Command::new("git")
+        .arg("init")
+        .arg(workspace.0.to_str().unwrap())
+        .output()
+        .expect("failed to execute 'git init'");
+    std::env::set_current_dir(&workspace.0)
+        .unwrap_or_else(|_| panic!("{}", workspace.0.to_str().unwrap()));
+    tracing::info!("start in {}", &workspace.0.as_os_str().to_str().unwrap());
+    let options = Options {
+        fix: args.fix,
+        commit: args.commit.clone(),
+    };
+    handler.execute(&args.project, &args.repository, args.id, &options)
+}
+
+fn write_report(args: &ReportArgs, report: Report) -> anyhow::Result<Report> {
+    let content = args.format.reporter().render(&report)?;
+    match &args.output {
+        Some(path) => std::fs::write(path, content)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?,
+        None => print!("{}", content),
+    }
+    if let Some(path) = &args.sarif {
+        let log = Format::Sarif.reporter().render(&report)?;
+        std::fs::write(path, log).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    }
+    Ok(report)
+}
+
+fn absolute_path(path: PathBuf) -> PathBuf {
+    std::env::current_dir()
+        .expect("cannot get work dir")
+        .join(path)
 }
 
 fn create_workspace(project: &str, repository: &str, id: u32) -> Workspace {